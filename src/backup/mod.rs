@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::fmt;
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use crate::database::{Database, GuildConfig, GuildLink, LinkedUser, NicknameSeen};
+
+const CSV_HEADER: &str = "discord_id,faceit_id,nickname,is_primary,linked_at";
+
+#[derive(poise::ChoiceParameter, Clone, Copy, Debug, PartialEq)]
+pub enum BackupFormat {
+    #[name = "json"]
+    Json,
+    #[name = "csv"]
+    Csv,
+}
+
+impl BackupFormat {
+
+    /// Guesses the format from a file name, JSON unless it ends in '.csv'.
+    pub fn from_file_name(name: &str) -> Self {
+        if name.to_lowercase().ends_with(".csv") {
+            BackupFormat::Csv
+        } else {
+            BackupFormat::Json
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BackupFormat::Json => "json",
+            BackupFormat::Csv => "csv",
+        }
+    }
+
+}
+
+/// What to do with links in the backup which disagree with the database.
+#[derive(poise::ChoiceParameter, Clone, Copy, Debug, PartialEq)]
+pub enum ConflictPolicy {
+    /// Keep the stored link.
+    #[name = "skip"]
+    Skip,
+    /// Replace the stored link with the one from the backup.
+    #[name = "overwrite"]
+    Overwrite,
+    /// Write nothing, only list what would be imported.
+    #[name = "report"]
+    Report,
+}
+
+/// Everything a JSON backup holds. CSV backups and backups made before guild settings were included only hold links.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Backup {
+    pub links: Vec<LinkedUser>,
+    #[serde(default)]
+    pub guild_links: Vec<GuildLink>,
+    #[serde(default)]
+    pub guild_config: Vec<GuildConfig>,
+    #[serde(default)]
+    pub nickname_history: Vec<NicknameSeen>,
+}
+
+#[derive(Default)]
+pub struct ImportReport {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Guild settings and moderator links which were written.
+    pub guild_settings: usize,
+    /// Past nicknames which were not stored yet.
+    pub past_nicknames: usize,
+    /// Description of every conflicting link.
+    pub conflicts: Vec<String>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Added: {}, Updated: {}, Unchanged: {}, Skipped: {}, Failed: {}, Guild settings: {}, Past nicknames: {}, Conflicts: {}",
+               self.added, self.updated, self.unchanged, self.skipped, self.failed, self.guild_settings, self.past_nicknames, self.conflicts.len())
+    }
+}
+
+/// The database in the given format, CSV only has room for the links.
+pub async fn export(database: Database, format: BackupFormat) -> Result<String, Error> {
+
+    let links = database.fetch_users().await?;
+
+    info!("Exporting {} links as {}.", links.len(), format.extension());
+
+    match format {
+        BackupFormat::Json => {
+            let backup = Backup {
+                links,
+                guild_links: database.fetch_guild_links().await?,
+                guild_config: database.fetch_guild_configs().await?,
+                nickname_history: database.fetch_nickname_history().await?,
+            };
+
+            Ok(serde_json::to_string_pretty(&backup)?)
+        },
+        BackupFormat::Csv => {
+            let mut csv = format!("{}\n", CSV_HEADER);
+
+            for link in links.iter() {
+                let fields = [
+                    link.discord_id.clone(),
+                    link.faceit_id.clone(),
+                    link.nickname.clone().unwrap_or_default(),
+                    (link.is_primary as i64).to_string(),
+                    link.linked_at.map(|at| at.to_string()).unwrap_or_default(),
+                ];
+                let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                csv.push_str(&row.join(","));
+                csv.push('\n');
+            }
+
+            Ok(csv)
+        }
+    }
+}
+
+pub fn parse(data: &str, format: BackupFormat) -> Result<Backup, Error> {
+
+    match format {
+        // Older backups are a bare list of links.
+        BackupFormat::Json if data.trim_start().starts_with('[') => Ok(Backup { links: serde_json::from_str(data)?, ..Default::default() }),
+        BackupFormat::Json => Ok(serde_json::from_str(data)?),
+        BackupFormat::Csv => {
+            let records = csv_records(data);
+            let mut lines = records.iter().filter(|line| !line.trim().is_empty());
+
+            if lines.next().map(|line| line.trim()) != Some(CSV_HEADER) {
+                return Err(anyhow!("CSV header has to be '{}'", CSV_HEADER));
+            }
+
+            let links = lines.enumerate().map(|(index, line)| {
+                let fields = parse_csv_line(line);
+
+                let [discord_id, faceit_id, nickname, is_primary, linked_at] = fields.as_slice() else {
+                    return Err(anyhow!("Line {} has {} fields, expected 5", index + 2, fields.len()));
+                };
+
+                Ok(LinkedUser {
+                    discord_id: discord_id.clone(),
+                    faceit_id: faceit_id.clone(),
+                    nickname: Some(nickname.clone()).filter(|nickname| !nickname.is_empty()),
+                    is_primary: is_primary == "1" || is_primary.eq_ignore_ascii_case("true"),
+                    linked_at: linked_at.parse().ok(),
+                })
+            }).collect::<Result<Vec<LinkedUser>, Error>>()?;
+
+            Ok(Backup { links, ..Default::default() })
+        }
+    }
+}
+
+/// Writes the backup to the database, handling entries which disagree with it as the policy says.
+pub async fn import(database: Database, backup: Backup, policy: ConflictPolicy) -> Result<ImportReport, Error> {
+
+    let mut report = ImportReport::default();
+
+    // The primary account each imported user should end up with, see `normalize_primary`.
+    let mut primaries: HashMap<String, Option<String>> = HashMap::new();
+
+    for link in backup.links.iter() {
+
+        if link.discord_id.parse::<u64>().is_err() || link.faceit_id.is_empty() {
+            report.failed += 1;
+            report.conflicts.push(format!("'{}' -> '{}': not a valid link.", link.discord_id, link.faceit_id));
+            continue;
+        }
+
+        let accounts = database.fetch_accounts(link.discord_id.clone()).await?;
+
+        let stored_primary = accounts.iter().find(|account| account.is_primary).map(|account| account.faceit_id.clone());
+
+        let stored = accounts.into_iter().find(|account| account.faceit_id == link.faceit_id);
+
+        let others: Vec<String> = database.fetch_discord_ids(link.faceit_id.clone()).await?
+            .into_iter()
+            .filter(|discord_id| *discord_id != link.discord_id)
+            .collect();
+
+        let conflict = match &stored {
+            Some(stored) if stored.nickname == link.nickname && stored.is_primary == link.is_primary && stored.linked_at == link.linked_at => {
+                report.unchanged += 1;
+                continue;
+            },
+            Some(_) => Some(format!("'{}' -> '{}': stored link differs.", link.discord_id, link.faceit_id)),
+            None if !others.is_empty() => Some(format!("'{}' -> '{}': account is linked to {}.", link.discord_id, link.faceit_id, others.join(", "))),
+            None => None,
+        };
+
+        if let Some(conflict) = conflict {
+            report.conflicts.push(conflict);
+
+            if policy != ConflictPolicy::Overwrite {
+                report.skipped += 1;
+                continue;
+            }
+        }
+
+        if policy == ConflictPolicy::Report {
+            report.added += 1;
+            continue;
+        }
+
+        for other in others.iter() {
+            database.unlink_account(other.clone(), link.faceit_id.clone()).await?;
+        }
+
+        match database.upsert_link(link).await {
+            Ok(_) if stored.is_some() || !others.is_empty() => report.updated += 1,
+            Ok(_) => report.added += 1,
+            Err(e) => {
+                error!("Could not import link '{}' -> '{}': {}", link.discord_id, link.faceit_id, e);
+                report.failed += 1;
+                continue;
+            }
+        }
+
+        // Skipping keeps the primary stored before the user's first write, which `upsert_link` hands to an imported primary.
+        // Overwriting takes the backup's.
+        let preferred = primaries.entry(link.discord_id.clone()).or_insert(match policy {
+            ConflictPolicy::Overwrite => None,
+            _ => stored_primary,
+        });
+
+        if link.is_primary && policy == ConflictPolicy::Overwrite {
+            *preferred = Some(link.faceit_id.clone());
+        }
+    }
+
+    for (discord_id, preferred) in primaries {
+        database.normalize_primary(discord_id, preferred).await?;
+    }
+
+    for config in backup.guild_config.iter() {
+
+        let stored = database.fetch_guild_config(config.guild_id.clone()).await?;
+
+        match &stored {
+            Some(stored) if stored == config => continue,
+            Some(_) => {
+                report.conflicts.push(format!("Guild '{}': stored settings differ.", config.guild_id));
+
+                if policy != ConflictPolicy::Overwrite {
+                    continue;
+                }
+            },
+            None => {},
+        }
+
+        if policy != ConflictPolicy::Report {
+            database.upsert_guild_config(config).await?;
+        }
+
+        report.guild_settings += 1;
+    }
+
+    for link in backup.guild_links.iter() {
+
+        let stored = database.fetch_guild_link(link.guild_id.clone(), link.discord_id.clone()).await?;
+
+        match &stored {
+            Some(stored) if stored.faceit_id == link.faceit_id => continue,
+            Some(_) => {
+                report.conflicts.push(format!("'{}' -> '{}' in guild '{}': stored moderator link differs.",
+                                              link.discord_id, link.faceit_id.as_deref().unwrap_or("unlinked"), link.guild_id));
+
+                if policy != ConflictPolicy::Overwrite {
+                    continue;
+                }
+            },
+            None => {},
+        }
+
+        if policy != ConflictPolicy::Report {
+            database.upsert_guild_link(link).await?;
+        }
+
+        report.guild_settings += 1;
+    }
+
+    if policy != ConflictPolicy::Report {
+        for seen in backup.nickname_history.iter() {
+            if database.add_nickname_seen(seen).await? {
+                report.past_nicknames += 1;
+            }
+        }
+    }
+
+    info!("Import finished. {}", report);
+
+    Ok(report)
+}
+
+/// Quotes a CSV field when it needs it.
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Splits CSV data into records, a quoted field may hold line breaks.
+fn csv_records(data: &str) -> Vec<&str> {
+
+    let mut records = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (index, c) in data.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '\n' if !quoted => {
+                records.push(&data[start..index]);
+                start = index + 1;
+            },
+            _ => {},
+        }
+    }
+
+    if start < data.len() {
+        records.push(&data[start..]);
+    }
+
+    records
+}
+
+fn parse_csv_line(line: &str) -> Vec<String> {
+
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+
+    fields.push(field);
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+
+        for field in ["plain", "", "with,comma", "with \"quotes\"", "\"", "line\nbreak", "all, \"of\"\nthem"] {
+            assert_eq!(parse_csv_line(&csv_field(field)), vec![field.to_string()], "field {:?}", field);
+        }
+    }
+
+    #[test]
+    fn only_fields_which_need_it_are_quoted() {
+
+        assert_eq!(csv_field("player"), "player");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn line_splits_on_unquoted_commas() {
+
+        assert_eq!(parse_csv_line("1,\"a,b\",,\"c\"\"d\"\r"), vec!["1", "a,b", "", "c\"d"]);
+    }
+
+    #[test]
+    fn records_keep_quoted_line_breaks() {
+
+        assert_eq!(csv_records("a,b\n\"c\nd\",e\n\nf\n"), vec!["a,b", "\"c\nd\",e", "", "f"]);
+    }
+
+    #[test]
+    fn parses_csv_backup() {
+
+        let link = LinkedUser {
+            discord_id: String::from("123"),
+            faceit_id: String::from("abc"),
+            nickname: Some(String::from("odd, \"nick\"\nname")),
+            is_primary: true,
+            linked_at: Some(1700000000),
+        };
+
+        let row: Vec<String> = [link.discord_id.as_str(), link.faceit_id.as_str(), link.nickname.as_deref().unwrap(), "1", "1700000000"]
+            .iter()
+            .map(|field| csv_field(field))
+            .collect();
+
+        let data = format!("{}\r\n{}\r\n\n124,def,,0,\n", CSV_HEADER, row.join(","));
+
+        let links = parse(&data, BackupFormat::Csv).unwrap().links;
+
+        assert_eq!(links.len(), 2);
+        assert_eq!((links[0].discord_id.as_str(), links[0].faceit_id.as_str()), ("123", "abc"));
+        assert_eq!(links[0].nickname, link.nickname);
+        assert!(links[0].is_primary);
+        assert_eq!(links[0].linked_at, Some(1700000000));
+        assert_eq!((links[1].nickname.as_deref(), links[1].is_primary, links[1].linked_at), (None, false, None));
+    }
+
+    #[test]
+    fn rejects_csv_without_header() {
+
+        assert!(parse("123,abc,,1,\n", BackupFormat::Csv).is_err());
+        assert!(parse(&format!("{}\n123,abc\n", CSV_HEADER), BackupFormat::Csv).is_err());
+    }
+
+}
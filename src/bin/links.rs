@@ -0,0 +1,84 @@
+//! Backs up and restores the link database without running the bot.
+//!
+//! Reads 'TURSO_DATABASE' and 'TURSO_TOKEN' from the environment.
+
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+use poise::ChoiceParameter;
+use plumpen::backup::{self, BackupFormat, ConflictPolicy};
+use plumpen::config::Config;
+use plumpen::database::Database;
+
+const USAGE: &str = "Usage:
+  links export <json|csv> [FILE]
+  links import FILE [skip|overwrite|report]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let database = match Config::load_database(|key| env::var(key).ok()) {
+        Ok(config) => Database::new(Box::leak(Box::new(config))),
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["export", format, rest @ ..] if rest.len() <= 1 => export(database, format, rest.first().copied()).await,
+        ["import", file, rest @ ..] if rest.len() <= 1 => import(database, file, rest.first().copied()).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn export(database: Database, format: &str, file: Option<&str>) -> Result<(), anyhow::Error> {
+
+    let format = BackupFormat::from_name(format).ok_or_else(|| anyhow::anyhow!("Unknown format '{}'", format))?;
+
+    database.migrate().await?;
+
+    let data = backup::export(database, format).await?;
+
+    match file {
+        Some(file) => fs::write(file, data)?,
+        None => print!("{}", data),
+    }
+
+    Ok(())
+}
+
+async fn import(database: Database, file: &str, policy: Option<&str>) -> Result<(), anyhow::Error> {
+
+    let policy = match policy {
+        Some(policy) => ConflictPolicy::from_name(policy).ok_or_else(|| anyhow::anyhow!("Unknown conflict policy '{}'", policy))?,
+        None => ConflictPolicy::Skip,
+    };
+
+    let backup = backup::parse(&fs::read_to_string(file)?, BackupFormat::from_file_name(file))?;
+
+    database.migrate().await?;
+
+    let report = backup::import(database, backup, policy).await?;
+
+    println!("{}", report);
+
+    for conflict in report.conflicts.iter() {
+        println!("- {}", conflict);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! Runs the bot under plain tokio, for self-hosting without Shuttle.
+//!
+//! Configuration uses the same keys as the Shuttle secrets. They are read from the environment,
+//! falling back to a TOML file given as the first argument, in 'PLUMPEN_CONFIG', or './plumpen.toml'.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+use tracing::{error, info};
+use plumpen::bot::Bot;
+use plumpen::config::Config;
+
+const DEFAULT_CONFIG_PATH: &str = "plumpen.toml";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+
+    tracing_subscriber::fmt::init();
+
+    let file = match read_config_file() {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Could not read config file: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = match Config::load(|key| env::var(key).ok().or_else(|| file_value(&file, key))) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut bot = match Bot::build(config).await {
+        Ok(bot) => bot,
+        Err(e) => {
+            error!("Could not start bot: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    bot.spawn_background();
+
+    info!("Starting standalone bot");
+
+    if let Err(e) = bot.client.start_autosharded().await {
+        error!("Client stopped: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    info!("Bot stopped");
+
+    ExitCode::SUCCESS
+}
+
+/// The config file as a table, empty when there is no config file.
+fn read_config_file() -> Result<toml::Table, anyhow::Error> {
+
+    let path = match env::args().nth(1).or(env::var("PLUMPEN_CONFIG").ok()) {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => String::from(DEFAULT_CONFIG_PATH),
+        None => return Ok(toml::Table::new()),
+    };
+
+    info!("Reading configuration from '{}'", path);
+
+    Ok(fs::read_to_string(&path)?.parse()?)
+}
+
+fn file_value(file: &toml::Table, key: &str) -> Option<String> {
+    match file.get(key)? {
+        toml::Value::String(value) => Some(value.clone()),
+        other => Some(other.to_string()),
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+use serenity::all::{Client, GatewayIntents};
+use tracing::{error, info};
+use crate::{discord, health, webhook, Data};
+use crate::config::Config;
+use crate::discord::DiscordBot;
+use crate::syncer::SyncScheduler;
+use crate::tasks::Supervisor;
+
+// How long in-flight syncs, commands and event handlers get to finish on shutdown before the shards are disconnected anyway.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Bot {
+    pub config: &'static Config,
+    pub client: Client,
+    pub scheduler: SyncScheduler,
+    pub supervisor: Supervisor,
+}
+
+impl Bot {
+
+    /// Migrates the database and builds the client, the configuration lives for as long as the process.
+    pub async fn build(config: Config) -> Result<Self, anyhow::Error> {
+
+        let config: &'static Config = Box::leak(Box::new(config));
+
+        config.database().migrate().await?;
+
+        let scheduler = SyncScheduler::new(config);
+        let data_scheduler = scheduler.clone();
+
+        let supervisor = Supervisor::default();
+        let data_supervisor = supervisor.clone();
+
+        let intents = GatewayIntents::GUILD_MEMBERS |
+            GatewayIntents::GUILD_MESSAGES |
+            GatewayIntents::DIRECT_MESSAGES |
+            GatewayIntents::MESSAGE_CONTENT |
+            GatewayIntents::GUILDS;
+
+        let framework = poise::Framework::builder()
+            .options(poise::FrameworkOptions {
+                commands: vec![
+                    discord::commands::help(),
+                    discord::commands::link(),
+                    discord::commands::verify(),
+                    discord::commands::unlink(),
+                    discord::commands::accounts(),
+                    discord::commands::stats(),
+                    discord::commands::refresh(),
+                    discord::commands::status(),
+                    discord::commands::announcements(),
+                    discord::commands::modlog(),
+                    discord::commands::banpolicy(),
+                    discord::commands::banned(),
+                    discord::commands::locationroles(),
+                    discord::commands::challengerrole(),
+                    discord::commands::diagnose(),
+                    discord::commands::moderatorrole(),
+                    discord::commands::nicknameformat(),
+                    discord::commands::modlink(),
+                    discord::commands::modunlink(),
+                    discord::commands::modrestore(),
+                    discord::commands::modleave(),
+                    discord::commands::failing(),
+                    discord::commands::guilds(),
+                    discord::commands::leave(),
+                    discord::commands::forceunlink(),
+                    discord::commands::forcelink(),
+                    discord::commands::restore(),
+                    discord::commands::export(),
+                    discord::commands::import(),
+                    discord::commands::admins(),
+                    discord::commands::addadmin(),
+                    discord::commands::removeadmin(),
+                    discord::commands::block(),
+                    discord::commands::unblock(),
+                    discord::commands::blocklist(),
+                    discord::commands::audit(),
+                ],
+                owners: config.owners.clone(),
+                // Kept until the invocation ends, error or not, so shutdown can wait for running commands.
+                pre_command: |ctx| Box::pin(async move {
+                    ctx.set_invocation_data(ctx.data().supervisor.track()).await;
+                }),
+                ..Default::default()
+            })
+            .setup(move |ctx, _ready, framework| {
+                Box::pin(async move {
+                    poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                    Ok(Data { config, scheduler: data_scheduler, supervisor: data_supervisor, refresh_cooldowns: Default::default() })
+                })
+            })
+            .build();
+
+        let client = Client::builder(&config.discord_token, intents)
+            .framework(framework)
+            .event_handler(DiscordBot { config, supervisor: supervisor.clone() })
+            .await?;
+
+        Ok(Bot { config, client, scheduler, supervisor })
+    }
+
+    /// Starts the supervised sync scheduler, webhook receiver and health server, and stops everything cleanly on SIGTERM or Ctrl-C.
+    pub fn spawn_background(&self) {
+
+        let scheduler = self.scheduler.clone();
+        let cache = self.client.cache.clone();
+        let http = self.client.http.clone();
+
+        self.supervisor.spawn("sync scheduler", move |shutdown| scheduler.clone().run(cache.clone(), http.clone(), shutdown));
+
+        let database = self.config.database();
+
+        if let Some(webhook_config) = self.config.webhook.clone() {
+            let scheduler = self.scheduler.clone();
+            let (finished, rosters) = webhook::queue();
+            self.supervisor.spawn("webhook receiver", move |shutdown| webhook::serve(webhook_config.clone(), finished.clone(), shutdown));
+            self.supervisor.spawn("webhook queue", move |shutdown| webhook::enqueue_finished(rosters.clone(), database, scheduler.clone(), shutdown));
+        }
+
+        if let Some(port) = self.config.health_port {
+            let shard_manager = self.client.shard_manager.clone();
+            let supervisor = self.supervisor.clone();
+            self.supervisor.spawn("health server", move |shutdown| health::serve(port, database, shard_manager.clone(), supervisor.clone(), shutdown));
+        }
+
+        let supervisor = self.supervisor.clone();
+        let shard_manager = self.client.shard_manager.clone();
+
+        tokio::spawn(async move {
+            wait_for_signal().await;
+
+            info!("Shutting down, letting background tasks and running commands finish");
+            supervisor.shutdown(SHUTDOWN_TIMEOUT).await;
+
+            info!("Disconnecting from Discord");
+            shard_manager.shutdown_all().await;
+        });
+    }
+
+}
+
+async fn wait_for_signal() {
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {},
+                    _ = tokio::signal::ctrl_c() => {},
+                }
+                return;
+            },
+            Err(e) => error!("Could not listen for SIGTERM: {}", e),
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Could not listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
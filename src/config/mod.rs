@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use serenity::all::UserId;
+use crate::database::{Database, DatabaseConfig};
+use crate::faceit::Faceit;
+use crate::ratelimit::RateBudget;
+use crate::discord::nickname::{NicknameTemplate, DEFAULT_TEMPLATE};
+use crate::syncer::SchedulerConfig;
+use crate::webhook::WebhookConfig;
+
+const DEFAULT_FACEIT_PER_MINUTE: u32 = 300;
+const DEFAULT_DISCORD_PER_MINUTE: u32 = 600;
+
+#[derive(Debug)]
+pub struct Config {
+    pub discord_token: String,
+    pub database: DatabaseConfig,
+    pub faceit_token: String,
+    /// Users allowed to run owner commands, from the comma separated 'BOT_OWNER'.
+    pub owners: HashSet<UserId>,
+    pub scheduler: SchedulerConfig,
+    /// Shared by every Faceit API request, commands included.
+    pub faceit_budget: RateBudget,
+    /// Shared by every Discord request the sync makes.
+    pub discord_budget: RateBudget,
+    /// Only set when 'WEBHOOK_SECRET' is.
+    pub webhook: Option<WebhookConfig>,
+    /// Used by guilds which haven't picked a nickname format.
+    pub nickname_template: String,
+    /// Port for '/healthz' and '/metrics', only served when 'HEALTH_PORT' is set.
+    pub health_port: Option<u16>,
+    /// Faceit accounts found to be banned are added to the blocklist, from 'BLOCK_FACEIT_BANNED'.
+    pub block_banned: bool,
+}
+
+/// Every problem found while loading the configuration, so they can all be fixed at once.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.0.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads keys through a lookup function and collects what is missing or malformed.
+struct Keys<F> {
+    lookup: F,
+    errors: Vec<String>,
+}
+
+impl<F> Keys<F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+
+    fn optional(&self, key: &str) -> Option<String> {
+        (self.lookup)(key).filter(|value| !value.trim().is_empty())
+    }
+
+    fn required(&mut self, key: &str) -> String {
+        self.optional(key).unwrap_or_else(|| {
+            self.errors.push(format!("'{}' is missing", key));
+            String::new()
+        })
+    }
+
+    fn parsed<T: FromStr>(&mut self, key: &str, default: T) -> T {
+        match self.optional(key) {
+            Some(value) => value.trim().parse().unwrap_or_else(|_| {
+                self.errors.push(format!("'{}' is not valid: '{}'", key, value));
+                default
+            }),
+            None => default,
+        }
+    }
+
+    fn secs(&mut self, key: &str, default: Duration) -> Duration {
+        Duration::from_secs(self.parsed(key, default.as_secs()))
+    }
+
+    fn finish<T>(self, value: T) -> Result<T, ConfigError> {
+        if self.errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(ConfigError(self.errors))
+        }
+    }
+
+}
+
+impl Config {
+
+    /// Loads and validates the configuration, `lookup` returns the value of a key if it is set.
+    pub fn load(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+
+        let mut keys = Keys { lookup, errors: Vec::new() };
+
+        let discord_token = keys.required("DISCORD_TOKEN");
+        let database = DatabaseConfig { url: keys.required("TURSO_DATABASE"), token: keys.required("TURSO_TOKEN") };
+        let faceit_token = keys.required("FACEIT_TOKEN");
+
+        let mut owners = HashSet::new();
+        for owner in keys.required("BOT_OWNER").split(',').map(str::trim).filter(|owner| !owner.is_empty()) {
+            match owner.parse::<u64>() {
+                Ok(u64_id) if u64_id != 0 => { owners.insert(UserId::new(u64_id)); },
+                _ => keys.errors.push(format!("'BOT_OWNER' contains an invalid user ID: '{}'", owner)),
+            }
+        }
+
+        let defaults = SchedulerConfig::default();
+        let scheduler = SchedulerConfig {
+            active_interval: keys.secs("SYNC_ACTIVE_INTERVAL_SECS", defaults.active_interval),
+            idle_interval: keys.secs("SYNC_IDLE_INTERVAL_SECS", defaults.idle_interval),
+            active_window: keys.secs("SYNC_ACTIVE_WINDOW_SECS", defaults.active_window),
+            reload_interval: keys.secs("SYNC_RELOAD_INTERVAL_SECS", defaults.reload_interval),
+        };
+
+        let faceit_budget = RateBudget::new(keys.parsed("FACEIT_REQUESTS_PER_MINUTE", DEFAULT_FACEIT_PER_MINUTE));
+        let discord_budget = RateBudget::new(keys.parsed("DISCORD_EDITS_PER_MINUTE", DEFAULT_DISCORD_PER_MINUTE));
+
+        let webhook = keys.optional("WEBHOOK_SECRET").map(|secret| WebhookConfig {
+            port: keys.parsed("WEBHOOK_PORT", 8080),
+            header: keys.optional("WEBHOOK_HEADER").unwrap_or(String::from("X-Webhook-Secret")),
+            secret,
+        });
+
+        let nickname_template = keys.optional("NICKNAME_TEMPLATE").unwrap_or(String::from(DEFAULT_TEMPLATE));
+        if let Err(reason) = NicknameTemplate::parse(&nickname_template) {
+            keys.errors.push(format!("'NICKNAME_TEMPLATE' is not valid: {}", reason));
+        }
+
+        let health_port = keys.optional("HEALTH_PORT").map(|_| keys.parsed("HEALTH_PORT", 0));
+
+        let block_banned = keys.parsed("BLOCK_FACEIT_BANNED", false);
+
+        keys.finish(Config { discord_token, database, faceit_token, owners, scheduler, faceit_budget, discord_budget, webhook, nickname_template, health_port, block_banned })
+    }
+
+    /// Loads only what the database needs, for tools which don't run the bot.
+    pub fn load_database(lookup: impl Fn(&str) -> Option<String>) -> Result<DatabaseConfig, ConfigError> {
+
+        let mut keys = Keys { lookup, errors: Vec::new() };
+
+        let database = DatabaseConfig { url: keys.required("TURSO_DATABASE"), token: keys.required("TURSO_TOKEN") };
+
+        keys.finish(database)
+    }
+
+    /// Database handle using this configuration.
+    pub fn database(&'static self) -> Database {
+        Database::new(&self.database)
+    }
+
+    /// Faceit client using this configuration's token and request budget.
+    pub fn faceit(&'static self) -> Faceit {
+        Faceit::new(&self.faceit_token, &self.faceit_budget)
+    }
+
+}
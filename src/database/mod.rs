@@ -1,33 +1,252 @@
-use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
 use anyhow::Error;
 use libsql::{Builder, Row};
-use serenity::all::User;
 use tracing::{error, info};
 use serde::{Deserialize, Serialize};
 
-pub struct Database;
+/// Handle to the database described by the configuration, cheap to copy.
+#[derive(Clone, Copy, Debug)]
+pub struct Database {
+    config: &'static DatabaseConfig,
+}
+
+#[derive(Clone, Debug)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub token: String,
+}
+
+// Applied in order on startup, the index of a statement is its schema version.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS users (discord_id TEXT NOT NULL, faceit_id TEXT NOT NULL);",
+    "CREATE TABLE IF NOT EXISTS link_challenges (discord_id TEXT PRIMARY KEY, faceit_id TEXT NOT NULL, code TEXT NOT NULL, expires_at INTEGER NOT NULL);",
+    "ALTER TABLE users ADD COLUMN nickname TEXT;",
+    "CREATE TABLE IF NOT EXISTS nickname_history (faceit_id TEXT NOT NULL, nickname TEXT NOT NULL, seen_at INTEGER NOT NULL);",
+    "CREATE TABLE IF NOT EXISTS guild_config (guild_id TEXT PRIMARY KEY, announce_channel TEXT);",
+    // Rebuild users so one Discord user can link several Faceit accounts.
+    "CREATE TABLE users_new (discord_id TEXT NOT NULL, faceit_id TEXT NOT NULL, nickname TEXT, is_primary INTEGER NOT NULL DEFAULT 1, PRIMARY KEY (discord_id, faceit_id));",
+    "INSERT OR IGNORE INTO users_new (discord_id, faceit_id, nickname) SELECT discord_id, faceit_id, nickname FROM users;",
+    "DROP TABLE users;",
+    "ALTER TABLE users_new RENAME TO users;",
+    "ALTER TABLE link_challenges ADD COLUMN make_primary INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE IF NOT EXISTS sync_status (guild_id TEXT NOT NULL, discord_id TEXT NOT NULL, status TEXT NOT NULL, detail TEXT, updated_at INTEGER NOT NULL, PRIMARY KEY (guild_id, discord_id));",
+    "ALTER TABLE guild_config ADD COLUMN moderator_role TEXT;",
+    "ALTER TABLE guild_config ADD COLUMN nickname_template TEXT;",
+    "ALTER TABLE users ADD COLUMN linked_at INTEGER;",
+    "CREATE TABLE IF NOT EXISTS bot_admins (discord_id TEXT PRIMARY KEY, added_by TEXT NOT NULL, added_at INTEGER NOT NULL);",
+    "CREATE TABLE IF NOT EXISTS audit_log (id INTEGER PRIMARY KEY AUTOINCREMENT, actor TEXT NOT NULL, action TEXT NOT NULL, discord_id TEXT, faceit_id TEXT, guild_id TEXT, created_at INTEGER NOT NULL);",
+    "ALTER TABLE guild_config ADD COLUMN modlog_channel TEXT;",
+    "CREATE TABLE IF NOT EXISTS blocklist (kind TEXT NOT NULL, id TEXT NOT NULL, reason TEXT, added_by TEXT NOT NULL, added_at INTEGER NOT NULL, PRIMARY KEY (kind, id));",
+    "CREATE TABLE IF NOT EXISTS faceit_bans (faceit_id TEXT PRIMARY KEY, banned INTEGER NOT NULL, reason TEXT, ends_at INTEGER, checked_at INTEGER NOT NULL);",
+    "ALTER TABLE guild_config ADD COLUMN ban_policy TEXT;",
+    "ALTER TABLE guild_config ADD COLUMN banned_role TEXT;",
+    "ALTER TABLE guild_config ADD COLUMN region_roles INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE guild_config ADD COLUMN country_roles INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE users ADD COLUMN link_code TEXT;",
+    "CREATE TABLE IF NOT EXISTS guild_links (guild_id TEXT NOT NULL, discord_id TEXT NOT NULL, faceit_id TEXT, linked_by TEXT NOT NULL, linked_at INTEGER NOT NULL, PRIMARY KEY (guild_id, discord_id));",
+    "ALTER TABLE guild_config ADD COLUMN challenger_role INTEGER NOT NULL DEFAULT 0;",
+];
+
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LinkedUser {
     pub faceit_id: String,
     pub discord_id: String,
+    /// Last Faceit nickname seen by the syncer, missing for links made before it was tracked.
+    #[serde(default)]
+    pub nickname: Option<String>,
+    /// The primary account drives the nickname and roles of the Discord user.
+    #[serde(default)]
+    pub is_primary: bool,
+    /// Missing for links made before it was tracked.
+    #[serde(default)]
+    pub linked_at: Option<i64>,
 }
 
 impl LinkedUser {
     fn from_row(row: &Row) -> Result<Self, Box<dyn std::error::Error>> {
         let faceit_id: String = row.get(0)?;
         let discord_id: String = row.get(1)?;
-        Ok(LinkedUser { faceit_id, discord_id })
+        let nickname: Option<String> = row.get(2)?;
+        let is_primary: i64 = row.get(3)?;
+        let linked_at: Option<i64> = row.get(4)?;
+        Ok(LinkedUser { faceit_id, discord_id, nickname, is_primary: is_primary != 0, linked_at })
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkChallenge {
+    pub faceit_id: String,
+    pub code: String,
+    pub expires_at: i64,
+    pub make_primary: bool,
+}
+
+/// A link made by a moderator, only applied in their guild and taking precedence over the member's own link there.
+/// A missing `faceit_id` means the moderator unlinked the member in the guild.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GuildLink {
+    pub guild_id: String,
+    pub discord_id: String,
+    pub faceit_id: Option<String>,
+    pub linked_by: String,
+    pub linked_at: i64,
+}
+
+impl GuildLink {
+    fn from_row(row: &Row) -> Result<Self, libsql::Error> {
+        Ok(GuildLink { guild_id: row.get(0)?, discord_id: row.get(1)?, faceit_id: row.get(2)?, linked_by: row.get(3)?, linked_at: row.get(4)? })
+    }
+}
+
+/// Every setting of a guild, as written by the guild configuration commands.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GuildConfig {
+    pub guild_id: String,
+    #[serde(default)]
+    pub announce_channel: Option<String>,
+    #[serde(default)]
+    pub modlog_channel: Option<String>,
+    #[serde(default)]
+    pub moderator_role: Option<String>,
+    #[serde(default)]
+    pub nickname_template: Option<String>,
+    #[serde(default)]
+    pub ban_policy: Option<String>,
+    #[serde(default)]
+    pub banned_role: Option<String>,
+    #[serde(default)]
+    pub region_roles: bool,
+    #[serde(default)]
+    pub country_roles: bool,
+    #[serde(default)]
+    pub challenger_role: bool,
+}
+
+impl GuildConfig {
+    fn from_row(row: &Row) -> Result<Self, libsql::Error> {
+        let region_roles: i64 = row.get(7)?;
+        let country_roles: i64 = row.get(8)?;
+        let challenger_role: i64 = row.get(9)?;
+        Ok(GuildConfig {
+            guild_id: row.get(0)?,
+            announce_channel: row.get(1)?,
+            modlog_channel: row.get(2)?,
+            moderator_role: row.get(3)?,
+            nickname_template: row.get(4)?,
+            ban_policy: row.get(5)?,
+            banned_role: row.get(6)?,
+            region_roles: region_roles != 0,
+            country_roles: country_roles != 0,
+            challenger_role: challenger_role != 0,
+        })
+    }
+}
+
+/// A Faceit nickname and when the syncer saw it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NicknameSeen {
+    pub faceit_id: String,
+    pub nickname: String,
+    pub seen_at: i64,
+}
+
+/// Latest sync outcome for a member in a guild.
+#[derive(Debug)]
+pub struct SyncStatus {
+    pub discord_id: String,
+    pub status: String,
+    pub detail: Option<String>,
+    pub updated_at: i64,
+}
+
+impl SyncStatus {
+    fn from_row(row: &Row) -> Result<Self, libsql::Error> {
+        Ok(SyncStatus { discord_id: row.get(0)?, status: row.get(1)?, detail: row.get(2)?, updated_at: row.get(3)? })
+    }
+}
+
+/// A Faceit player ID or Discord user ID which may not be linked, `kind` is 'faceit' or 'discord'.
+#[derive(Debug)]
+pub struct BlockedEntry {
+    pub kind: String,
+    pub id: String,
+    pub reason: Option<String>,
+    pub added_by: String,
+    pub added_at: i64,
+}
+
+/// Last known Faceit ban status of an account, `ends_at` is missing for permanent bans.
+#[derive(Debug)]
+pub struct FaceitBan {
+    pub banned: bool,
+    pub reason: Option<String>,
+    pub ends_at: Option<i64>,
+    pub checked_at: i64,
+}
+
+impl FaceitBan {
+
+    pub fn is_active(&self, now: i64) -> bool {
+        self.banned && self.ends_at.is_none_or(|ends_at| ends_at > now)
+    }
+
+}
+
+/// A linked member whose account in a guild has a Faceit ban.
+#[derive(Debug)]
+pub struct BannedMember {
+    pub discord_id: String,
+    pub nickname: Option<String>,
+    pub reason: Option<String>,
+    pub ends_at: Option<i64>,
+}
+
+/// Who did what to whom, and when.
+#[derive(Debug)]
+pub struct AuditEntry {
+    pub actor: String,
+    pub action: String,
+    pub discord_id: Option<String>,
+    pub faceit_id: Option<String>,
+    pub guild_id: Option<String>,
+    pub created_at: i64,
+}
+
+impl AuditEntry {
+    fn from_row(row: &Row) -> Result<Self, libsql::Error> {
+        Ok(AuditEntry {
+            actor: row.get(0)?,
+            action: row.get(1)?,
+            discord_id: row.get(2)?,
+            faceit_id: row.get(3)?,
+            guild_id: row.get(4)?,
+            created_at: row.get(5)?,
+        })
     }
 }
 
+/// Narrows down audit entries, unset fields match everything.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub discord_id: Option<String>,
+    pub guild_id: Option<String>,
+}
+
 impl Database {
 
-    async fn connect() -> libsql::Database {
+    pub fn new(config: &'static DatabaseConfig) -> Self {
+        Database { config }
+    }
+
+    async fn connect(&self) -> libsql::Database {
 
-        // Just panic if these aren't set.
-        let url = env::var("TURSO_DATABASE").expect("Failed to get TURSO_DATABASE!");
-        let token = env::var("TURSO_TOKEN").expect("Failed to get TURSO_TOKEN!");
+        let DatabaseConfig { url, token } = self.config.clone();
 
         // @TODO Add more error handling later when the rewrite is done
         Builder::new_remote(url, token)
@@ -35,9 +254,46 @@ impl Database {
             .await.expect("Could not connect to database")
     }
 
+    pub async fn migrate(&self) -> Result<(), Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        con.execute("CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY);", ()).await?;
+
+        let mut result = con.query("SELECT COALESCE(MAX(version), -1) FROM schema_migrations;", ()).await?;
+
+        let current: i64 = match result.next().await? {
+            Some(row) => row.get(0)?,
+            None => -1,
+        };
+
+        for (version, statement) in MIGRATIONS.iter().enumerate().skip((current + 1) as usize) {
+            info!("Applying database migration {}.", version);
+            con.execute(statement, ()).await?;
+            con.execute("INSERT INTO schema_migrations (version) VALUES (:version);",
+                        libsql::named_params! { ":version": version as i64 }).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the database answers.
+    pub async fn ping(&self) -> Result<(), Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        con.query("SELECT 1;", ()).await?;
+
+        Ok(())
+    }
+
     pub async fn user_exists(&self, discord_id: String) -> Result<bool, Error> {
 
-        let db: libsql::Database = Self::connect().await;
+        let db: libsql::Database = self.connect().await;
 
         let con = db.connect()?;
 
@@ -51,26 +307,210 @@ impl Database {
 
     }
 
-    pub async fn add_user(&self, faceit_id: String, discord_id: String) -> Result<bool, Error> {
+    /// Links a Faceit account, the first account of a Discord user always becomes primary.
+    pub async fn add_user(&self, faceit_id: String, discord_id: String, nickname: Option<String>, make_primary: bool) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT COUNT(*) FROM users WHERE discord_id = :discord_id;",
+                                   libsql::named_params! { ":discord_id": discord_id.clone() }).await?;
+
+        let existing: i64 = match result.next().await? {
+            Some(row) => row.get(0)?,
+            None => 0,
+        };
+
+        let is_primary = make_primary || existing == 0;
+
+        if is_primary {
+            con.execute("UPDATE users SET is_primary = 0 WHERE discord_id = :discord_id;",
+                        libsql::named_params! { ":discord_id": discord_id.clone() }).await?;
+        }
+
+        let results = con.execute("INSERT INTO users (discord_id, faceit_id, nickname, is_primary, linked_at) VALUES (:discord_id, :faceit_id, :nickname, :is_primary, :linked_at)",
+                    libsql::named_params! { ":discord_id": discord_id, ":faceit_id": faceit_id.clone(), ":nickname": nickname.clone(), ":is_primary": is_primary as i64, ":linked_at": unix_now() }).await?;
+
+        if let Some(nickname) = nickname {
+            con.execute("INSERT INTO nickname_history (faceit_id, nickname, seen_at) VALUES (:faceit_id, :nickname, :seen_at);",
+                        libsql::named_params! { ":faceit_id": faceit_id, ":nickname": nickname, ":seen_at": unix_now() }).await?;
+        }
+
+        Ok(results != 0)
+    }
+
+    /// Remembers the verification code of a link, so the syncer doesn't announce it leaving the Faceit nickname.
+    pub async fn set_link_code(&self, faceit_id: String, discord_id: String, code: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("UPDATE users SET link_code = :code WHERE discord_id = :discord_id AND faceit_id = :faceit_id;",
+                                  libsql::named_params! { ":discord_id": discord_id, ":faceit_id": faceit_id, ":code": code }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// The verification code of a link whose nickname hasn't changed since it was verified.
+    pub async fn fetch_link_code(&self, faceit_id: String) -> Result<Option<String>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT link_code FROM users WHERE faceit_id = :faceit_id AND link_code IS NOT NULL LIMIT 1;",
+                                   libsql::named_params! { ":faceit_id": faceit_id }).await?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes a link as is, replacing the stored one for the same account. Used when importing a backup.
+    pub async fn upsert_link(&self, link: &LinkedUser) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        if link.is_primary {
+            con.execute("UPDATE users SET is_primary = 0 WHERE discord_id = :discord_id;",
+                        libsql::named_params! { ":discord_id": link.discord_id.clone() }).await?;
+        }
+
+        let results = con.execute("INSERT INTO users (discord_id, faceit_id, nickname, is_primary, linked_at) VALUES (:discord_id, :faceit_id, :nickname, :is_primary, :linked_at) \
+                                   ON CONFLICT(discord_id, faceit_id) DO UPDATE SET nickname = excluded.nickname, is_primary = excluded.is_primary, linked_at = excluded.linked_at;",
+                                  libsql::named_params! { ":discord_id": link.discord_id.clone(), ":faceit_id": link.faceit_id.clone(), ":nickname": link.nickname.clone(),
+                                                          ":is_primary": link.is_primary as i64, ":linked_at": link.linked_at }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn account_linked(&self, discord_id: String, faceit_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT 1 FROM users WHERE discord_id = :discord_id AND faceit_id = :faceit_id;",
+                                   libsql::named_params! { ":discord_id": discord_id, ":faceit_id": faceit_id }).await?;
+
+        Ok(result.next().await?.is_some())
+    }
+
+    /// Returns every account linked to a Discord user, primary account first.
+    pub async fn fetch_accounts(&self, discord_id: String) -> Result<Vec<LinkedUser>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT faceit_id, discord_id, nickname, is_primary, linked_at FROM users WHERE discord_id = :discord_id ORDER BY is_primary DESC, rowid;",
+                                 libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        let mut accounts = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            match LinkedUser::from_row(&row) {
+                Ok(account) => accounts.push(account),
+                Err(err) => error!("Failed to parse row to LinkedUser: {}", err)
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /// Discord users who linked a Faceit account, as primary or not.
+    pub async fn fetch_discord_ids(&self, faceit_id: String) -> Result<Vec<String>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT discord_id FROM users WHERE faceit_id = :faceit_id;",
+                                 libsql::named_params! { ":faceit_id": faceit_id }).await?;
+
+        let mut discord_ids = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            discord_ids.push(row.get(0)?);
+        }
+
+        Ok(discord_ids)
+    }
+
+    pub async fn set_primary(&self, discord_id: String, faceit_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("UPDATE users SET is_primary = (faceit_id = :faceit_id) WHERE discord_id = :discord_id;",
+                                  libsql::named_params! { ":discord_id": discord_id, ":faceit_id": faceit_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Unlinks a single account, promoting the oldest remaining account if the primary was removed.
+    pub async fn unlink_account(&self, discord_id: String, faceit_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("DELETE FROM users WHERE discord_id = :discord_id AND faceit_id = :faceit_id;",
+                                  libsql::named_params! { ":discord_id": discord_id.clone(), ":faceit_id": faceit_id }).await?;
+
+        con.execute("UPDATE users SET is_primary = 1 WHERE rowid = (SELECT rowid FROM users WHERE discord_id = :discord_id ORDER BY rowid LIMIT 1) \
+                     AND NOT EXISTS (SELECT 1 FROM users WHERE discord_id = :discord_id AND is_primary = 1);",
+                    libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Stores a new Faceit nickname for every link to the account and keeps the old one in the history.
+    pub async fn update_nickname(&self, faceit_id: String, nickname: String) -> Result<bool, Error> {
 
-        let db: libsql::Database = Self::connect().await;
+        let db: libsql::Database = self.connect().await;
 
         let con = db.connect()?;
 
-        let results = con.execute("INSERT INTO users (discord_id, faceit_id) VALUES (:discord_id, :faceit_id)",
-                    libsql::named_params! { ":discord_id": discord_id, ":faceit_id": faceit_id }).await?;
+        let results = con.execute("UPDATE users SET nickname = :nickname, link_code = NULL WHERE faceit_id = :faceit_id;",
+                                  libsql::named_params! { ":faceit_id": faceit_id.clone(), ":nickname": nickname.clone() }).await?;
+
+        con.execute("INSERT INTO nickname_history (faceit_id, nickname, seen_at) VALUES (:faceit_id, :nickname, :seen_at);",
+                    libsql::named_params! { ":faceit_id": faceit_id, ":nickname": nickname, ":seen_at": unix_now() }).await?;
 
         Ok(results != 0)
     }
 
+    /// Finds the Faceit ID which most recently used a nickname, case insensitive.
+    pub async fn find_faceit_id_by_past_nickname(&self, nickname: String) -> Result<Option<String>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT faceit_id FROM nickname_history WHERE nickname = :nickname COLLATE NOCASE ORDER BY seen_at DESC LIMIT 1;",
+                                   libsql::named_params! { ":nickname": nickname }).await?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
     pub async fn unlink_user(&self, discord_id: String) -> Result<bool, Error> {
 
-        let db: libsql::Database = Self::connect().await;
+        let db: libsql::Database = self.connect().await;
 
         let con = db.connect()?;
 
         let results = con.execute("DELETE FROM users WHERE discord_id = :discord_id;",
-                                  libsql::named_params! { ":discord_id": discord_id}).await?;
+                                  libsql::named_params! { ":discord_id": discord_id }).await?;
 
         Ok(results != 0)
 
@@ -78,13 +518,13 @@ impl Database {
 
     pub async fn count_users(&self) -> Result<i64, Error> {
 
-        let db: libsql::Database = Self::connect().await;
+        let db: libsql::Database = self.connect().await;
 
         let con = db.connect()?;
 
-        let mut result = con.query("SELECT COUNT(*) FROM users;", ()).await?;
+        let mut result = con.query("SELECT COUNT(DISTINCT discord_id) FROM users;", ()).await?;
 
-        while let Some(row) = result.next().await? {
+        if let Some(row) = result.next().await? {
             let count: i64 = row.get(0)?;
             return Ok(count);
         }
@@ -95,11 +535,11 @@ impl Database {
 
     pub async fn fetch_users(&self) -> Result<Vec<LinkedUser>, Error> {
 
-        let db: libsql::Database = Self::connect().await;
+        let db: libsql::Database = self.connect().await;
 
         let con = db.connect()?;
 
-        let mut rows = con.query("SELECT faceit_id, discord_id FROM users", ()).await?;
+        let mut rows = con.query("SELECT faceit_id, discord_id, nickname, is_primary, linked_at FROM users", ()).await?;
 
         let mut users = Vec::new();
 
@@ -116,4 +556,676 @@ impl Database {
         Ok(users)
     }
 
+    pub async fn set_announce_channel(&self, guild_id: String, channel_id: Option<String>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, announce_channel) VALUES (:guild_id, :channel_id) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET announce_channel = excluded.announce_channel;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":channel_id": channel_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Returns (guild_id, channel_id) for every guild which has announcements enabled.
+    pub async fn fetch_announce_channels(&self) -> Result<Vec<(String, String)>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT guild_id, announce_channel FROM guild_config WHERE announce_channel IS NOT NULL;", ()).await?;
+
+        let mut channels = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            channels.push((row.get(0)?, row.get(1)?));
+        }
+
+        Ok(channels)
+    }
+
+    pub async fn set_moderator_role(&self, guild_id: String, role_id: Option<String>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, moderator_role) VALUES (:guild_id, :role_id) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET moderator_role = excluded.moderator_role;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":role_id": role_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn fetch_moderator_role(&self, guild_id: String) -> Result<Option<String>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT moderator_role FROM guild_config WHERE guild_id = :guild_id;",
+                                 libsql::named_params! { ":guild_id": guild_id }).await?;
+
+        match rows.next().await? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_nickname_template(&self, guild_id: String, template: Option<String>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, nickname_template) VALUES (:guild_id, :template) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET nickname_template = excluded.nickname_template;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":template": template }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn add_bot_admin(&self, discord_id: String, added_by: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT OR IGNORE INTO bot_admins (discord_id, added_by, added_at) VALUES (:discord_id, :added_by, :added_at);",
+                                  libsql::named_params! { ":discord_id": discord_id, ":added_by": added_by, ":added_at": unix_now() }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn remove_bot_admin(&self, discord_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("DELETE FROM bot_admins WHERE discord_id = :discord_id;",
+                                  libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn is_bot_admin(&self, discord_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT 1 FROM bot_admins WHERE discord_id = :discord_id;",
+                                   libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        Ok(result.next().await?.is_some())
+    }
+
+    /// Returns (discord_id, added_by) for every bot admin, oldest first.
+    pub async fn fetch_bot_admins(&self) -> Result<Vec<(String, String)>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT discord_id, added_by FROM bot_admins ORDER BY added_at;", ()).await?;
+
+        let mut admins = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            admins.push((row.get(0)?, row.get(1)?));
+        }
+
+        Ok(admins)
+    }
+
+    pub async fn add_challenge(&self, discord_id: String, faceit_id: String, code: String, expires_at: i64, make_primary: bool) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT OR REPLACE INTO link_challenges (discord_id, faceit_id, code, expires_at, make_primary) VALUES (:discord_id, :faceit_id, :code, :expires_at, :make_primary);",
+                    libsql::named_params! { ":discord_id": discord_id, ":faceit_id": faceit_id, ":code": code, ":expires_at": expires_at, ":make_primary": make_primary as i64 }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Returns the pending challenge for a Discord user, expired challenges are removed first.
+    pub async fn fetch_challenge(&self, discord_id: String) -> Result<Option<LinkChallenge>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        con.execute("DELETE FROM link_challenges WHERE expires_at < :now;",
+                    libsql::named_params! { ":now": unix_now() }).await?;
+
+        let mut result = con.query("SELECT faceit_id, code, expires_at, make_primary FROM link_challenges WHERE discord_id = :discord_id;",
+                                   libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        match result.next().await? {
+            Some(row) => {
+                let make_primary: i64 = row.get(3)?;
+                Ok(Some(LinkChallenge { faceit_id: row.get(0)?, code: row.get(1)?, expires_at: row.get(2)?, make_primary: make_primary != 0 }))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub async fn remove_challenge(&self, discord_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("DELETE FROM link_challenges WHERE discord_id = :discord_id;",
+                                  libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn record_sync_status(&self, guild_id: String, discord_id: String, status: String, detail: Option<String>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT OR REPLACE INTO sync_status (guild_id, discord_id, status, detail, updated_at) VALUES (:guild_id, :discord_id, :status, :detail, :updated_at);",
+                                  libsql::named_params! { ":guild_id": guild_id, ":discord_id": discord_id, ":status": status, ":detail": detail, ":updated_at": unix_now() }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Forgets the member's sync status, for members who left or were cleared.
+    pub async fn remove_sync_status(&self, guild_id: String, discord_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("DELETE FROM sync_status WHERE guild_id = :guild_id AND discord_id = :discord_id;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":discord_id": discord_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn fetch_sync_status(&self, guild_id: String, discord_id: String) -> Result<Option<SyncStatus>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT discord_id, status, detail, updated_at FROM sync_status WHERE guild_id = :guild_id AND discord_id = :discord_id;",
+                                   libsql::named_params! { ":guild_id": guild_id, ":discord_id": discord_id }).await?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(SyncStatus::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Members of a guild whose latest sync did not succeed.
+    pub async fn fetch_failing_members(&self, guild_id: String) -> Result<Vec<SyncStatus>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT discord_id, status, detail, updated_at FROM sync_status \
+                                  WHERE guild_id = :guild_id AND status NOT IN ('edited', 'unchanged') ORDER BY updated_at DESC;",
+                                 libsql::named_params! { ":guild_id": guild_id }).await?;
+
+        let mut statuses = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            statuses.push(SyncStatus::from_row(&row)?);
+        }
+
+        Ok(statuses)
+    }
+
+    pub async fn add_audit_entry(&self, entry: &AuditEntry) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO audit_log (actor, action, discord_id, faceit_id, guild_id, created_at) \
+                                   VALUES (:actor, :action, :discord_id, :faceit_id, :guild_id, :created_at);",
+                                  libsql::named_params! {
+                                      ":actor": entry.actor.clone(),
+                                      ":action": entry.action.clone(),
+                                      ":discord_id": entry.discord_id.clone(),
+                                      ":faceit_id": entry.faceit_id.clone(),
+                                      ":guild_id": entry.guild_id.clone(),
+                                      ":created_at": entry.created_at,
+                                  }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Newest entries first.
+    pub async fn fetch_audit_entries(&self, filter: AuditFilter, limit: i64) -> Result<Vec<AuditEntry>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT actor, action, discord_id, faceit_id, guild_id, created_at FROM audit_log \
+                                  WHERE (:actor IS NULL OR actor = :actor) AND (:action IS NULL OR action = :action) \
+                                  AND (:discord_id IS NULL OR discord_id = :discord_id) AND (:guild_id IS NULL OR guild_id = :guild_id) \
+                                  ORDER BY id DESC LIMIT :limit;",
+                                 libsql::named_params! {
+                                     ":actor": filter.actor,
+                                     ":action": filter.action,
+                                     ":discord_id": filter.discord_id,
+                                     ":guild_id": filter.guild_id,
+                                     ":limit": limit,
+                                 }).await?;
+
+        let mut entries = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            entries.push(AuditEntry::from_row(&row)?);
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn set_modlog_channel(&self, guild_id: String, channel_id: Option<String>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, modlog_channel) VALUES (:guild_id, :channel_id) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET modlog_channel = excluded.modlog_channel;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":channel_id": channel_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Returns (guild_id, channel_id) for every guild which has a mod-log channel.
+    pub async fn fetch_modlog_channels(&self) -> Result<Vec<(String, String)>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT guild_id, modlog_channel FROM guild_config WHERE modlog_channel IS NOT NULL;", ()).await?;
+
+        let mut channels = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            channels.push((row.get(0)?, row.get(1)?));
+        }
+
+        Ok(channels)
+    }
+
+    /// Returns (guild_id, channel_id) for every guild with a mod-log channel the member was last synced in.
+    pub async fn fetch_member_modlog_channels(&self, discord_id: String) -> Result<Vec<(String, String)>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT guild_config.guild_id, guild_config.modlog_channel FROM guild_config \
+                                  JOIN sync_status ON sync_status.guild_id = guild_config.guild_id \
+                                  WHERE sync_status.discord_id = :discord_id AND guild_config.modlog_channel IS NOT NULL;",
+                                 libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        let mut channels = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            channels.push((row.get(0)?, row.get(1)?));
+        }
+
+        Ok(channels)
+    }
+
+    pub async fn add_block(&self, kind: &str, id: String, reason: Option<String>, added_by: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT OR IGNORE INTO blocklist (kind, id, reason, added_by, added_at) VALUES (:kind, :id, :reason, :added_by, :added_at);",
+                                  libsql::named_params! { ":kind": kind, ":id": id, ":reason": reason, ":added_by": added_by, ":added_at": unix_now() }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn remove_block(&self, kind: &str, id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("DELETE FROM blocklist WHERE kind = :kind AND id = :id;",
+                                  libsql::named_params! { ":kind": kind, ":id": id }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Whether the Discord user or the Faceit account is blocked.
+    pub async fn is_blocked(&self, discord_id: String, faceit_id: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT 1 FROM blocklist WHERE (kind = 'discord' AND id = :discord_id) OR (kind = 'faceit' AND id = :faceit_id) LIMIT 1;",
+                                 libsql::named_params! { ":discord_id": discord_id, ":faceit_id": faceit_id }).await?;
+
+        Ok(rows.next().await?.is_some())
+    }
+
+    pub async fn fetch_blocklist(&self) -> Result<Vec<BlockedEntry>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT kind, id, reason, added_by, added_at FROM blocklist ORDER BY added_at DESC;", ()).await?;
+
+        let mut entries = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            entries.push(BlockedEntry { kind: row.get(0)?, id: row.get(1)?, reason: row.get(2)?, added_by: row.get(3)?, added_at: row.get(4)? });
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn fetch_ban(&self, faceit_id: String) -> Result<Option<FaceitBan>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT banned, reason, ends_at, checked_at FROM faceit_bans WHERE faceit_id = :faceit_id;",
+                                 libsql::named_params! { ":faceit_id": faceit_id }).await?;
+
+        match rows.next().await? {
+            Some(row) => {
+                let banned: i64 = row.get(0)?;
+                Ok(Some(FaceitBan { banned: banned != 0, reason: row.get(1)?, ends_at: row.get(2)?, checked_at: row.get(3)? }))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub async fn record_ban(&self, faceit_id: String, banned: bool, reason: Option<String>, ends_at: Option<i64>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO faceit_bans (faceit_id, banned, reason, ends_at, checked_at) VALUES (:faceit_id, :banned, :reason, :ends_at, :checked_at) \
+                                   ON CONFLICT(faceit_id) DO UPDATE SET banned = excluded.banned, reason = excluded.reason, ends_at = excluded.ends_at, checked_at = excluded.checked_at;",
+                                  libsql::named_params! { ":faceit_id": faceit_id, ":banned": banned as i64, ":reason": reason, ":ends_at": ends_at, ":checked_at": unix_now() }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Members of the guild whose account there is banned, as far as the sync status knows who is a member.
+    /// A moderator's link in the guild comes before the member's primary account.
+    pub async fn fetch_banned_members(&self, guild_id: String) -> Result<Vec<BannedMember>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT sync_status.discord_id, \
+                                  COALESCE(CASE WHEN guild_links.discord_id IS NULL THEN users.nickname END, \
+                                  (SELECT nickname FROM nickname_history WHERE nickname_history.faceit_id = faceit_bans.faceit_id ORDER BY seen_at DESC LIMIT 1)) AS nickname, \
+                                  faceit_bans.reason, faceit_bans.ends_at FROM sync_status \
+                                  LEFT JOIN guild_links ON guild_links.guild_id = sync_status.guild_id AND guild_links.discord_id = sync_status.discord_id \
+                                  LEFT JOIN users ON users.discord_id = sync_status.discord_id AND users.is_primary = 1 \
+                                  JOIN faceit_bans ON faceit_bans.faceit_id = CASE WHEN guild_links.discord_id IS NULL THEN users.faceit_id ELSE guild_links.faceit_id END \
+                                  WHERE sync_status.guild_id = :guild_id AND faceit_bans.banned = 1 AND (faceit_bans.ends_at IS NULL OR faceit_bans.ends_at > :now) \
+                                  ORDER BY nickname;",
+                                 libsql::named_params! { ":guild_id": guild_id, ":now": unix_now() }).await?;
+
+        let mut members = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            members.push(BannedMember { discord_id: row.get(0)?, nickname: row.get(1)?, reason: row.get(2)?, ends_at: row.get(3)? });
+        }
+
+        Ok(members)
+    }
+
+    pub async fn set_ban_policy(&self, guild_id: String, policy: String, role_id: Option<String>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, ban_policy, banned_role) VALUES (:guild_id, :policy, :role_id) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET ban_policy = excluded.ban_policy, banned_role = excluded.banned_role;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":policy": policy, ":role_id": role_id }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn set_location_roles(&self, guild_id: String, region: bool, country: bool) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, region_roles, country_roles) VALUES (:guild_id, :region, :country) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET region_roles = excluded.region_roles, country_roles = excluded.country_roles;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":region": region as i64, ":country": country as i64 }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn set_challenger_role(&self, guild_id: String, enabled: bool) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, challenger_role) VALUES (:guild_id, :enabled) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET challenger_role = excluded.challenger_role;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":enabled": enabled as i64 }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Links or unlinks a member in a single guild, replacing what a moderator set before.
+    pub async fn set_guild_link(&self, guild_id: String, discord_id: String, faceit_id: Option<String>, linked_by: String) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_links (guild_id, discord_id, faceit_id, linked_by, linked_at) VALUES (:guild_id, :discord_id, :faceit_id, :linked_by, :linked_at) \
+                                   ON CONFLICT(guild_id, discord_id) DO UPDATE SET faceit_id = excluded.faceit_id, linked_by = excluded.linked_by, linked_at = excluded.linked_at;",
+                                  libsql::named_params! { ":guild_id": guild_id, ":discord_id": discord_id, ":faceit_id": faceit_id, ":linked_by": linked_by, ":linked_at": unix_now() }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn fetch_guild_link(&self, guild_id: String, discord_id: String) -> Result<Option<GuildLink>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT guild_id, discord_id, faceit_id, linked_by, linked_at FROM guild_links WHERE guild_id = :guild_id AND discord_id = :discord_id;",
+                                   libsql::named_params! { ":guild_id": guild_id, ":discord_id": discord_id }).await?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(GuildLink::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every guild link of a member.
+    pub async fn fetch_member_guild_links(&self, discord_id: String) -> Result<Vec<GuildLink>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT guild_id, discord_id, faceit_id, linked_by, linked_at FROM guild_links WHERE discord_id = :discord_id;",
+                                 libsql::named_params! { ":discord_id": discord_id }).await?;
+
+        let mut links = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            links.push(GuildLink::from_row(&row)?);
+        }
+
+        Ok(links)
+    }
+
+    pub async fn fetch_guild_links(&self) -> Result<Vec<GuildLink>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT guild_id, discord_id, faceit_id, linked_by, linked_at FROM guild_links;", ()).await?;
+
+        let mut links = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            links.push(GuildLink::from_row(&row)?);
+        }
+
+        Ok(links)
+    }
+
+    /// Writes a guild link as is, replacing the stored one. Used when importing a backup.
+    pub async fn upsert_guild_link(&self, link: &GuildLink) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_links (guild_id, discord_id, faceit_id, linked_by, linked_at) VALUES (:guild_id, :discord_id, :faceit_id, :linked_by, :linked_at) \
+                                   ON CONFLICT(guild_id, discord_id) DO UPDATE SET faceit_id = excluded.faceit_id, linked_by = excluded.linked_by, linked_at = excluded.linked_at;",
+                                  libsql::named_params! { ":guild_id": link.guild_id.clone(), ":discord_id": link.discord_id.clone(), ":faceit_id": link.faceit_id.clone(),
+                                                          ":linked_by": link.linked_by.clone(), ":linked_at": link.linked_at }).await?;
+
+        Ok(results != 0)
+    }
+
+    /// Leaves the user with exactly one primary account, `preferred` if it is linked and otherwise the oldest primary or link.
+    pub async fn normalize_primary(&self, discord_id: String, preferred: Option<String>) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("UPDATE users SET is_primary = (rowid = (SELECT rowid FROM users WHERE discord_id = :discord_id \
+                                   ORDER BY faceit_id IS :preferred DESC, is_primary DESC, rowid LIMIT 1)) WHERE discord_id = :discord_id;",
+                                  libsql::named_params! { ":discord_id": discord_id, ":preferred": preferred }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn fetch_guild_configs(&self) -> Result<Vec<GuildConfig>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT guild_id, announce_channel, modlog_channel, moderator_role, nickname_template, ban_policy, banned_role, region_roles, country_roles, challenger_role \
+                                  FROM guild_config;", ()).await?;
+
+        let mut configs = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            configs.push(GuildConfig::from_row(&row)?);
+        }
+
+        Ok(configs)
+    }
+
+    pub async fn fetch_guild_config(&self, guild_id: String) -> Result<Option<GuildConfig>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut result = con.query("SELECT guild_id, announce_channel, modlog_channel, moderator_role, nickname_template, ban_policy, banned_role, region_roles, country_roles, challenger_role \
+                                    FROM guild_config WHERE guild_id = :guild_id;",
+                                   libsql::named_params! { ":guild_id": guild_id }).await?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(GuildConfig::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes every setting of a guild, replacing the stored ones. Used when importing a backup.
+    pub async fn upsert_guild_config(&self, config: &GuildConfig) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO guild_config (guild_id, announce_channel, modlog_channel, moderator_role, nickname_template, ban_policy, banned_role, region_roles, country_roles, challenger_role) \
+                                   VALUES (:guild_id, :announce_channel, :modlog_channel, :moderator_role, :nickname_template, :ban_policy, :banned_role, :region_roles, :country_roles, :challenger_role) \
+                                   ON CONFLICT(guild_id) DO UPDATE SET announce_channel = excluded.announce_channel, modlog_channel = excluded.modlog_channel, \
+                                   moderator_role = excluded.moderator_role, nickname_template = excluded.nickname_template, ban_policy = excluded.ban_policy, \
+                                   banned_role = excluded.banned_role, region_roles = excluded.region_roles, country_roles = excluded.country_roles, \
+                                   challenger_role = excluded.challenger_role;",
+                                  libsql::named_params! {
+                                      ":guild_id": config.guild_id.clone(),
+                                      ":announce_channel": config.announce_channel.clone(),
+                                      ":modlog_channel": config.modlog_channel.clone(),
+                                      ":moderator_role": config.moderator_role.clone(),
+                                      ":nickname_template": config.nickname_template.clone(),
+                                      ":ban_policy": config.ban_policy.clone(),
+                                      ":banned_role": config.banned_role.clone(),
+                                      ":region_roles": config.region_roles as i64,
+                                      ":country_roles": config.country_roles as i64,
+                                      ":challenger_role": config.challenger_role as i64,
+                                  }).await?;
+
+        Ok(results != 0)
+    }
+
+    pub async fn fetch_nickname_history(&self) -> Result<Vec<NicknameSeen>, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let mut rows = con.query("SELECT faceit_id, nickname, seen_at FROM nickname_history ORDER BY seen_at;", ()).await?;
+
+        let mut history = Vec::new();
+
+        while let Some(row) = rows.next().await? {
+            history.push(NicknameSeen { faceit_id: row.get(0)?, nickname: row.get(1)?, seen_at: row.get(2)? });
+        }
+
+        Ok(history)
+    }
+
+    /// Adds a past nickname unless the exact same sighting is stored already, returns whether it was added.
+    pub async fn add_nickname_seen(&self, seen: &NicknameSeen) -> Result<bool, Error> {
+
+        let db: libsql::Database = self.connect().await;
+
+        let con = db.connect()?;
+
+        let results = con.execute("INSERT INTO nickname_history (faceit_id, nickname, seen_at) SELECT :faceit_id, :nickname, :seen_at \
+                                   WHERE NOT EXISTS (SELECT 1 FROM nickname_history WHERE faceit_id = :faceit_id AND nickname = :nickname AND seen_at = :seen_at);",
+                                  libsql::named_params! { ":faceit_id": seen.faceit_id.clone(), ":nickname": seen.nickname.clone(), ":seen_at": seen.seen_at }).await?;
+
+        Ok(results != 0)
+    }
+
 }
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use serenity::all::{CacheHttp, GuildId, Http, Member, PartialGuild, Permissions, RoleId};
+use tracing::error;
+use crate::metrics;
+use super::ALL_ROLES;
+
+// Owners of misconfigured guilds are reminded at most this often.
+const REPORT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+static REPORTED_GUILDS: LazyLock<Mutex<HashMap<GuildId, Instant>>> = LazyLock::new(Default::default);
+
+/// What the bot is allowed to change in a guild.
+pub(crate) struct GuildAccess {
+    pub manage_nicknames: bool,
+    pub manage_roles: bool,
+    /// Position of the bot's highest role, only members and roles below it can be managed.
+    pub top_position: u16,
+}
+
+impl GuildAccess {
+
+    /// Computes the bot's effective guild permissions, using the cached bot member when available.
+    pub async fn resolve<T>(cache_http: &T, guild: &PartialGuild) -> Option<Self>
+    where
+        T: CacheHttp,
+    {
+
+        let cached_roles = cache_http.cache().and_then(|cache| {
+            let bot_id = cache.current_user().id;
+            let cached_guild = cache.guild(guild.id)?;
+            cached_guild.members.get(&bot_id).map(|member| member.roles.clone())
+        });
+
+        metrics::CACHE_LOOKUPS.inc(if cached_roles.is_some() { "hit" } else { "miss" });
+
+        let bot_roles = match cached_roles {
+            Some(roles) => roles,
+            None => {
+                let http: &Http = cache_http.http();
+                let current_user = http.get_current_user().await.ok()?;
+                guild.member(http, current_user.id).await.ok()?.roles
+            }
+        };
+
+        // The @everyone role shares its ID with the guild.
+        let mut permissions = guild.roles.get(&RoleId::new(guild.id.get()))
+            .map(|role| role.permissions)
+            .unwrap_or(Permissions::empty());
+
+        let mut top_position = 0;
+
+        for role_id in bot_roles.iter() {
+            if let Some(role) = guild.roles.get(role_id) {
+                permissions |= role.permissions;
+                top_position = top_position.max(role.position);
+            }
+        }
+
+        let administrator = permissions.administrator();
+
+        Some(GuildAccess {
+            manage_nicknames: administrator || permissions.manage_nicknames(),
+            manage_roles: administrator || permissions.manage_roles(),
+            top_position,
+        })
+    }
+
+    /// Discord only allows editing members whose highest role is below the bot's.
+    pub fn can_edit_member(&self, guild: &PartialGuild, member: &Member) -> bool {
+        let member_position = member.roles.iter()
+            .filter_map(|role_id| guild.roles.get(role_id))
+            .map(|role| role.position)
+            .max()
+            .unwrap_or(0);
+
+        member_position < self.top_position
+    }
+
+    pub fn can_assign(&self, guild: &PartialGuild, role_id: RoleId) -> bool {
+        self.manage_roles && guild.roles.get(&role_id).is_some_and(|role| role.position < self.top_position)
+    }
+
+    /// Problems which keep the bot from syncing members in the guild, empty if there are none.
+    pub fn problems(&self, guild: &PartialGuild) -> Vec<String> {
+
+        let mut problems = Vec::new();
+
+        if !self.manage_nicknames {
+            problems.push(String::from("The bot is missing the 'Manage Nicknames' permission."));
+        }
+
+        if !self.manage_roles {
+            problems.push(String::from("The bot is missing the 'Manage Roles' permission."));
+        }
+
+        let mut misplaced: Vec<&str> = guild.roles.values()
+            .filter(|role| ALL_ROLES.contains(&role.name.as_str()) && role.position >= self.top_position)
+            .map(|role| role.name.as_str())
+            .collect();
+
+        if !misplaced.is_empty() {
+            misplaced.sort();
+            problems.push(format!("These roles have to be moved below the bot's role: {}.", misplaced.join(", ")));
+        }
+
+        problems
+    }
+
+}
+
+/// Tells the guild owner what has to be fixed, at most once per `REPORT_INTERVAL`.
+pub(crate) async fn report_problems(http: &Http, guild: &PartialGuild, problems: &[String]) {
+
+    {
+        let Ok(mut reported) = REPORTED_GUILDS.lock() else { return };
+
+        if reported.get(&guild.id).is_some_and(|at| at.elapsed() < REPORT_INTERVAL) {
+            return;
+        }
+
+        reported.insert(guild.id, Instant::now());
+    }
+
+    error!("Guild '{}' is misconfigured: {}", guild.name, problems.join(" "));
+
+    let message = format!("Plumpen can't keep nicknames and roles up to date in **{}**:\n- {}", guild.name, problems.join("\n- "));
+
+    let result = match guild.owner_id.create_dm_channel(http).await {
+        Ok(channel) => channel.say(http, message).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        error!("Could not report problems to owner of guild '{}': {}", guild.name, e);
+    }
+
+}
@@ -0,0 +1,181 @@
+use std::time::Duration;
+use poise::ChoiceParameter;
+use serenity::all::{ChannelId, GuildId, Http, UserId};
+use tokio::time::sleep;
+use tracing::error;
+use crate::config::Config;
+use crate::database::{unix_now, AuditEntry};
+
+#[derive(poise::ChoiceParameter, Clone, Copy, Debug, PartialEq)]
+pub enum AuditAction {
+    #[name = "link"]
+    Link,
+    #[name = "unlink"]
+    Unlink,
+    #[name = "forcelink"]
+    ForceLink,
+    #[name = "forceunlink"]
+    ForceUnlink,
+    #[name = "modlink"]
+    ModLink,
+    #[name = "modunlink"]
+    ModUnlink,
+    #[name = "restore"]
+    Restore,
+    #[name = "leave"]
+    Leave,
+    #[name = "addadmin"]
+    AddAdmin,
+    #[name = "removeadmin"]
+    RemoveAdmin,
+    #[name = "block"]
+    Block,
+    #[name = "unblock"]
+    Unblock,
+}
+
+impl AuditAction {
+
+    /// The bot is no longer in the guild after leaving, so there is no mod-log to post in.
+    fn is_mirrored(&self) -> bool {
+        *self != AuditAction::Leave
+    }
+
+}
+
+/// An action to audit, `guild_id` is left out for actions which affect every guild.
+pub(crate) struct AuditEvent {
+    pub actor: UserId,
+    pub action: AuditAction,
+    pub discord_id: Option<UserId>,
+    pub faceit_id: Option<String>,
+    pub guild_id: Option<GuildId>,
+}
+
+impl AuditEvent {
+
+    fn to_entry(&self) -> AuditEntry {
+        AuditEntry {
+            actor: self.actor.to_string(),
+            action: self.action.name().to_string(),
+            discord_id: self.discord_id.map(|id| id.to_string()),
+            faceit_id: self.faceit_id.clone(),
+            guild_id: self.guild_id.map(|id| id.to_string()),
+            created_at: unix_now(),
+        }
+    }
+
+}
+
+/// One line describing an entry, used for the mod-log and '/audit'.
+pub(crate) fn describe(entry: &AuditEntry) -> String {
+
+    let mut line = format!("<t:{}:f> <@{}> used '{}'", entry.created_at, entry.actor, entry.action);
+
+    if let Some(discord_id) = &entry.discord_id {
+        line.push_str(&format!(" on <@{}>", discord_id));
+    }
+
+    if let Some(faceit_id) = &entry.faceit_id {
+        line.push_str(&format!(", **Faceit ID**: '{}'", faceit_id));
+    }
+
+    if let Some(guild_id) = &entry.guild_id {
+        line.push_str(&format!(", **Guild**: '{}'", guild_id));
+    }
+
+    line.push('.');
+    line
+}
+
+/// Stores the event without posting it anywhere.
+pub(crate) async fn store(config: &'static Config, event: &AuditEvent) -> Option<AuditEntry> {
+
+    let entry = event.to_entry();
+
+    if let Err(e) = config.database().add_audit_entry(&entry).await {
+        error!("Error storing audit entry for '{}': {}", entry.action, e);
+        return None;
+    }
+
+    Some(entry)
+}
+
+/// Stores the event and posts it in the mod-log of its guild, or of every guild the target was last synced in.
+pub(crate) async fn record<T>(config: &'static Config, http_t: T, event: AuditEvent)
+where
+    T: AsRef<Http>,
+{
+
+    let Some(entry) = store(config, &event).await else {
+        return;
+    };
+
+    if !event.action.is_mirrored() {
+        return;
+    }
+
+    let message = describe(&entry);
+
+    match (event.guild_id, event.discord_id) {
+        (Some(guild_id), _) => post(config, http_t, guild_id, &message).await,
+        (None, Some(discord_id)) => notify(config, http_t, discord_id, &message).await,
+        (None, None) => {},
+    }
+}
+
+/// Posts a message in the mod-log of every guild the user was last synced in.
+pub(crate) async fn notify<T>(config: &'static Config, http_t: T, discord_id: UserId, message: &str)
+where
+    T: AsRef<Http>,
+{
+
+    let http: &Http = http_t.as_ref();
+
+    // Membership comes from the stored sync status instead of asking Discord once per guild.
+    let Ok(channels) = config.database().fetch_member_modlog_channels(discord_id.to_string()).await else {
+        error!("Error attempting to get mod-log channels.");
+        return;
+    };
+
+    for (guild_id, channel_id) in channels.iter() {
+
+        let Ok(channel_id) = channel_id.parse::<u64>() else {
+            continue;
+        };
+
+        if let Err(e) = ChannelId::new(channel_id).say(http, message).await {
+            error!("Error posting in mod-log of guild '{}': {}", guild_id, e);
+        }
+
+        sleep(Duration::from_millis(30)).await;
+
+    }
+}
+
+/// Posts a message in the mod-log of a guild, if it has one.
+pub(crate) async fn post<T>(config: &'static Config, http_t: T, guild_id: GuildId, message: &str)
+where
+    T: AsRef<Http>,
+{
+
+    let http: &Http = http_t.as_ref();
+
+    let Ok(channels) = config.database().fetch_modlog_channels().await else {
+        error!("Error attempting to get mod-log channels.");
+        return;
+    };
+
+    let guild = guild_id.to_string();
+
+    for (_, channel_id) in channels.iter().filter(|(id, _)| *id == guild) {
+
+        let Ok(channel_id) = channel_id.parse::<u64>() else {
+            continue;
+        };
+
+        if let Err(e) = ChannelId::new(channel_id).say(http, message).await {
+            error!("Error posting in mod-log of guild '{}': {}", guild_id, e);
+        }
+    }
+}
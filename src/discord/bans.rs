@@ -0,0 +1,127 @@
+use poise::ChoiceParameter;
+use serenity::all::{RoleId, UserId};
+use tracing::{error, info};
+use crate::config::Config;
+use crate::database::{unix_now, GuildConfig};
+use crate::faceit::Player;
+
+// Bans change rarely, so they are checked far less often than ELO.
+const BAN_CHECK_INTERVAL_SECS: i64 = 6 * 60 * 60;
+
+/// Stored as `added_by` of accounts blocked for a Faceit ban rather than by an admin.
+pub(crate) const FACEIT_BAN_BLOCKER: &str = "faceit";
+
+/// How a guild treats members whose primary Faceit account is banned.
+#[derive(poise::ChoiceParameter, Clone, Copy, Debug, PartialEq)]
+pub enum BanPolicy {
+    /// Banned players keep their rank role.
+    #[name = "ignore"]
+    Ignore,
+    /// Banned players lose their rank role.
+    #[name = "removerank"]
+    RemoveRank,
+    /// Banned players get the banned role in place of their rank role.
+    #[name = "role"]
+    Role,
+}
+
+pub(crate) struct GuildBanConfig {
+    pub policy: BanPolicy,
+    pub role: Option<RoleId>,
+}
+
+impl GuildBanConfig {
+
+    /// The guild's settings, guilds which never picked a policy ignore bans.
+    pub fn from_config(guild: &GuildConfig) -> Self {
+        GuildBanConfig {
+            policy: guild.ban_policy.as_deref().and_then(BanPolicy::from_name).unwrap_or(BanPolicy::Ignore),
+            role: guild.banned_role.as_ref().and_then(|role| role.parse::<u64>().ok()).map(RoleId::new),
+        }
+    }
+
+    /// The role a member should get in place of their rank role.
+    pub fn role_for(&self, banned: bool, rank_role: Option<RoleId>) -> Option<RoleId> {
+        match (banned, self.policy) {
+            (false, _) | (true, BanPolicy::Ignore) => rank_role,
+            (true, BanPolicy::RemoveRank) => None,
+            (true, BanPolicy::Role) => self.role,
+        }
+    }
+
+}
+
+/// Whether the stored ban status of the player is an active ban, see `refresh`.
+pub(crate) async fn is_banned(config: &'static Config, player: &Player) -> bool {
+    match config.database().fetch_ban(player.player_id.to_string()).await {
+        Ok(ban) => ban.is_some_and(|ban| ban.is_active(unix_now())),
+        Err(e) => {
+            error!("Could not fetch ban status of Faceit user '{}': {}", player.player_id, e);
+            false
+        }
+    }
+}
+
+/// Asks Faceit for the player's bans if they haven't been checked lately, returning a notice for the mod-logs if the player was newly banned.
+/// Newly banned accounts are also blocked if the owner set 'BLOCK_FACEIT_BANNED'.
+pub(crate) async fn refresh(config: &'static Config, discord_id: UserId, player: &Player) -> Option<String> {
+
+    let now = unix_now();
+
+    let stored = match config.database().fetch_ban(player.player_id.to_string()).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("Could not fetch ban status of Faceit user '{}': {}", player.player_id, e);
+            return None;
+        }
+    };
+
+    if stored.as_ref().is_some_and(|ban| now - ban.checked_at < BAN_CHECK_INTERVAL_SECS) {
+        return None;
+    }
+
+    let bans = match config.faceit().get_player_bans(&player.player_id).await {
+        Ok(bans) => bans,
+        Err(e) => {
+            error!("Could not fetch bans of Faceit user '{}': {}", player.player_id, e);
+            return None;
+        }
+    };
+
+    let active = bans.iter().find(|ban| ban.is_active(now));
+
+    let reason = active.map(|ban| if ban.reason.is_empty() { ban.kind.clone() } else { ban.reason.clone() });
+    let ends_at = active.and_then(|ban| ban.ends_at_unix());
+
+    if let Err(e) = config.database().record_ban(player.player_id.to_string(), active.is_some(), reason.clone(), ends_at).await {
+        error!("Could not store ban status of Faceit user '{}': {}", player.player_id, e);
+        return None;
+    }
+
+    let was_banned = stored.is_some_and(|ban| ban.is_active(now));
+
+    if active.is_none() || was_banned {
+        return None;
+    }
+
+    info!("Faceit user '{}' is banned: {}", player.nickname, reason.as_deref().unwrap_or("unknown"));
+
+    let until = match ends_at {
+        Some(ends_at) => format!("until <t:{}:f>", ends_at),
+        None => String::from("permanently"),
+    };
+
+    let mut notice = format!("<@{}> is banned on Faceit as '{}' {}, **Reason**: '{}'.",
+                             discord_id, player.nickname, until, reason.as_deref().unwrap_or("unknown"));
+
+    if config.block_banned {
+        let block_reason = format!("Banned on Faceit: {}", reason.as_deref().unwrap_or("unknown"));
+        match config.database().add_block("faceit", player.player_id.to_string(), Some(block_reason), String::from(FACEIT_BAN_BLOCKER)).await {
+            Ok(true) => notice.push_str(" The account was blocked."),
+            Ok(false) => {},
+            Err(e) => error!("Could not block banned Faceit user '{}': {}", player.player_id, e),
+        }
+    }
+
+    Some(notice)
+}
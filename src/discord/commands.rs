@@ -1,10 +1,27 @@
-use std::sync::Arc;
-use regex::Regex;
-use serenity::all::{GuildId, UserId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use poise::{ChoiceParameter, CreateReply, ReplyHandle};
+use serenity::all::{Attachment, ButtonStyle, ComponentInteractionCollector, CreateActionRow, CreateAttachment, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage, GuildChannel, GuildId, RoleId, User, UserId};
 use tracing::{error, info};
-use crate::{Context, Error, PoiseContext};
-use crate::database::Database;
-use crate::discord::DiscordBot;
+use crate::{Error, PoiseContext};
+use crate::backup::{self, BackupFormat, ConflictPolicy};
+use crate::config::Config;
+use crate::database::{unix_now, AuditFilter};
+use crate::discord::{self, describe_status, DiscordBot};
+use crate::discord::audit::{self, AuditAction, AuditEvent};
+use crate::discord::bans::{BanPolicy, FACEIT_BAN_BLOCKER};
+use crate::discord::location;
+use crate::discord::nickname::NicknameTemplate;
+use crate::discord::restore::{RestorePlan, RestoreSummary};
+
+// How often a user can refresh themselves.
+const REFRESH_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+// How long a restore preview can be confirmed.
+const RESTORE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// How often the restore status message is edited while scanning.
+const RESTORE_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
 // Displays all commands
 #[poise::command(prefix_command, track_edits, slash_command)]
@@ -27,100 +44,1105 @@ pub async fn help(
 }
 
 /// Links to Faceit account using Faceit username
+///
+/// Gives a code which has to be added to the Faceit nickname, then confirmed using '!verify'.
+/// Several accounts can be linked, the primary account decides nickname and role.
+/// Faceit only allows a few nickname changes, adding the code and removing it afterwards uses up two of them.
 #[poise::command(prefix_command, track_edits, slash_command)]
 pub async fn link(
     ctx: PoiseContext<'_>,
-    #[description = "Faceit username"] username: String
+    #[description = "Faceit username"] username: String,
+    #[description = "Make this your primary account"] #[flag] primary: bool
+) -> Result<(), Error> {
+
+    let author = ctx.author();
+
+    match DiscordBot::create_link_challenge(ctx.data().config, &username, ctx, author.id, primary, &ctx).await {
+        Ok(Some(code)) => {
+            info!("Created link challenge for user: {}", author.name);
+            ctx.say(format!("To prove you own Faceit account '{}', add the code **{}** to your Faceit nickname and run '!verify' within 15 minutes. \
+                             You can change your nickname back afterwards.", username, code)).await?;
+        },
+        Ok(None) => {
+            info!("No link challenge created for Discord user '{}' and Faceit account '{}'", author.name, username);
+            if primary {
+                // The account may already have been linked and just became primary.
+                ctx.data().scheduler.enqueue(author.id).await;
+            }
+        },
+        Err(e) => {
+            ctx.say(format!("Error when attempting to link Discord user '{}' to Faceit account '{}'.", author.name, username)).await?;
+            error!("Error creating link challenge {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Confirms a pending link once the code is in the Faceit nickname
+#[poise::command(prefix_command, track_edits, slash_command)]
+pub async fn verify(
+    ctx: PoiseContext<'_>
+) -> Result<(), Error> {
+
+    let author = ctx.author();
+
+    match DiscordBot::verify_link(ctx.data().config, ctx, author.id, &ctx).await {
+        Ok(success) => {
+            if success {
+                info!("Successfully linked user: {}", author.name);
+                ctx.data().scheduler.enqueue(author.id).await;
+                ctx.say(format!("Successfully linked Discord user '{}'. You can now remove the code from your Faceit nickname.", author.name)).await?;
+            } else {
+                error!("Error verifying link for Discord user '{}'", author.name);
+            }
+        },
+        Err(e) => {
+            ctx.say(format!("Error when attempting to verify link for Discord user '{}'.", author.name)).await?;
+            error!("Error verifying user {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Unlinks from Faceit account
+///
+/// Unlinks every account unless a specific Faceit account is given.
+#[poise::command(prefix_command, track_edits, slash_command)]
+pub async fn unlink(
+    ctx: PoiseContext<'_>,
+    #[description = "Faceit username of a single account"] account: Option<String>
+) -> Result<(), Error> {
+
+    let author = ctx.author();
+
+    let Ok(accounts) = ctx.data().config.database().fetch_accounts(author.id.to_string()).await else {
+        error!("Error fetching linked accounts");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(())
+    };
+
+    if accounts.is_empty() {
+        ctx.say("User not linked. Please link using '!link *faceitUsername*'").await?;
+        return Ok(())
+    };
+
+    if let Some(account) = account {
+
+        let Some(linked) = accounts.iter().find(|linked| {
+            linked.faceit_id == account || linked.nickname.as_deref().is_some_and(|nickname| nickname.eq_ignore_ascii_case(&account))
+        }) else {
+            ctx.say(format!("Faceit account '{}' is not linked, see '!accounts'.", account)).await?;
+            return Ok(())
+        };
+
+        let Ok(success) = ctx.data().config.database().unlink_account(author.id.to_string(), linked.faceit_id.clone()).await else {
+            ctx.say(format!("Error when attempting to unlink account '{}'.", account)).await?;
+            error!("Error unlinking account");
+            return Ok(())
+        };
+
+        if !success {
+            ctx.say(format!("Error when attempting to unlink account '{}'.", account)).await?;
+            error!("Error unlinking account");
+            return Ok(())
+        }
+
+        // Recorded before clearing, the mod-logs are found through the member's sync status.
+        audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: author.id, action: AuditAction::Unlink, discord_id: Some(author.id), faceit_id: Some(linked.faceit_id.clone()), guild_id: None }).await;
+
+        ctx.say(format!("Successfully unlinked account '{}'.", account)).await?;
+
+        ctx.data().scheduler.enqueue(author.id).await;
+
+        match DiscordBot::sync_primary(ctx.data().config, ctx, author.id).await {
+            Ok(true) => {},
+            Ok(false) => {
+                info!("Attempting to clear nickname in all relevant guilds.");
+                DiscordBot::clear_user(ctx.data().config, ctx, author.id).await;
+            },
+            Err(e) => error!("Error syncing primary account {}", e),
+        }
+
+        return Ok(())
+    }
+
+    let primary = accounts.into_iter().find(|linked| linked.is_primary).map(|linked| linked.faceit_id);
+
+    let Ok(success) = ctx.data().config.database().unlink_user(author.id.to_string()).await else {
+        ctx.say(format!("Error when attempting to unlink user '{}'.", author.name)).await?;
+        error!("Error unlinking user");
+        return Ok(())
+    };
+
+    if success {
+        audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: author.id, action: AuditAction::Unlink, discord_id: Some(author.id), faceit_id: primary, guild_id: None }).await;
+        ctx.data().scheduler.enqueue(author.id).await;
+        ctx.say(format!("Successfully unlinked user '{}'.", author.name)).await?;
+        info!("Attempting to clear nickname in all relevant guilds.");
+        DiscordBot::clear_user(ctx.data().config, ctx, author.id).await;
+    } else {
+        ctx.say(format!("Error when attempting to unlink user '{}'.", author.name)).await?;
+        error!("Error unlinking user");
+    }
+
+    Ok(())
+}
+
+/// Lists your linked Faceit accounts
+#[poise::command(prefix_command, track_edits, slash_command)]
+pub async fn accounts(
+    ctx: PoiseContext<'_>
 ) -> Result<(), Error> {
 
     let author = ctx.author();
 
+    let Ok(accounts) = ctx.data().config.database().fetch_accounts(author.id.to_string()).await else {
+        error!("Error fetching linked accounts");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(())
+    };
+
+    if accounts.is_empty() {
+        ctx.say("User not linked. Please link using '!link *faceitUsername*'").await?;
+        return Ok(())
+    };
+
+    let mut message = String::from("# Linked accounts \n");
+
+    for account in accounts.iter() {
+        let nickname = account.nickname.as_deref().unwrap_or("Unknown");
+        let marker = if account.is_primary { " (primary)" } else { "" };
+        message.push_str(format!("**Faceit**: '{}'{}, **ID**: '{}'.\n", nickname, marker, account.faceit_id).as_str());
+    }
+
+    ctx.say(message).await?;
+
+    Ok(())
+}
+
+/// Displays Faceit stats for a linked user
+#[poise::command(prefix_command, track_edits, slash_command)]
+pub async fn stats(
+    ctx: PoiseContext<'_>,
+    #[description = "Discord user, defaults to yourself"] user: Option<User>
+) -> Result<(), Error> {
+
+    let target = user.as_ref().unwrap_or_else(|| ctx.author());
+
+    let Ok(accounts) = ctx.data().config.database().fetch_accounts(target.id.to_string()).await else {
+        error!("Error fetching linked accounts");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(())
+    };
+
+    if accounts.is_empty() {
+        ctx.say(format!("User '{}' is not linked.", target.name)).await?;
+        return Ok(())
+    };
+
+    let mut message = format!("# Stats for {} \n", target.name);
+
+    for account in accounts.iter() {
+
+        let label = if account.is_primary { "Primary" } else { "Other" };
+
+        match ctx.data().config.faceit().get_faceit_user_by_id(&account.faceit_id).await {
+            Ok(Some(player)) => {
+                let elo = player.get_player_elo().unwrap_or(String::from("?"));
+                let level = player.get_player_skill_level().map(|level| level.to_string()).unwrap_or(String::from("?"));
+                message.push_str(format!("**{}**: '{}', **Level**: {}, **ELO**: {}.\n", label, player.nickname, level, elo).as_str());
+            },
+            Ok(None) => {
+                message.push_str(format!("**{}**: '{}', not found on Faceit.\n", label, account.faceit_id).as_str());
+            },
+            Err(e) => {
+                error!("Error fetching Faceit user {}", e);
+                message.push_str(format!("**{}**: '{}', could not reach Faceit.\n", label, account.faceit_id).as_str());
+            }
+        }
+
+    }
+
+    ctx.say(message).await?;
+
+    Ok(())
+}
+
+/// Updates nickname and role from Faceit right away
+///
+/// Moderators can refresh another member, or every linked member of the guild.
+#[poise::command(prefix_command, track_edits, slash_command)]
+pub async fn refresh(
+    ctx: PoiseContext<'_>,
+    #[description = "Member to refresh (moderators only)"] user: Option<User>,
+    #[description = "Refresh every linked member of this guild (moderators only)"] #[flag] guild: bool
+) -> Result<(), Error> {
+
+    if (user.is_some() || guild) && !is_guild_moderator(ctx).await {
+        ctx.say("Only moderators of this guild can refresh other members.").await?;
+        return Ok(());
+    }
+
+    if guild {
+        let Some(guild_id) = ctx.guild_id() else {
+            return Ok(());
+        };
+
+        ctx.defer().await?;
+        info!("Refreshing every linked member of guild '{}'.", guild_id);
+        DiscordBot::sync_guild(ctx.data().config, ctx, guild_id).await;
+        ctx.say("Refreshed every linked member of this guild.").await?;
+        return Ok(());
+    }
+
+    // Taken before the refresh starts so refreshes fired while it runs are turned away, failed refreshes give it back.
+    let mut cooldown_started = None;
+
+    let target = match &user {
+        Some(user) => user,
+        None => {
+            let author = ctx.author();
+
+            let remaining = {
+                let mut cooldowns = ctx.data().refresh_cooldowns.lock().unwrap_or_else(|e| e.into_inner());
+
+                let remaining = cooldowns.get(&author.id)
+                    .map(|at| REFRESH_COOLDOWN.saturating_sub(at.elapsed()))
+                    .filter(|remaining| !remaining.is_zero());
+
+                if remaining.is_none() {
+                    let now = Instant::now();
+                    cooldowns.insert(author.id, now);
+                    cooldown_started = Some(now);
+                }
+
+                remaining
+            };
+
+            if let Some(remaining) = remaining {
+                ctx.say(format!("Please wait {} seconds before refreshing again.", remaining.as_secs() + 1)).await?;
+                return Ok(());
+            }
+
+            author
+        }
+    };
+
+    ctx.defer().await?;
+
+    // Only the guild the command was used in is refreshed, direct messages have no guild to pick so every guild is.
+    let synced = match ctx.guild_id() {
+        Some(guild_id) => DiscordBot::sync_member(ctx.data().config, ctx, guild_id, target.id).await,
+        None => DiscordBot::sync_primary(ctx.data().config, ctx, target.id).await,
+    };
+
+    if let (Some(started), Ok(false) | Err(_)) = (cooldown_started, &synced) {
+        let mut cooldowns = ctx.data().refresh_cooldowns.lock().unwrap_or_else(|e| e.into_inner());
+        if cooldowns.get(&target.id) == Some(&started) {
+            cooldowns.remove(&target.id);
+        }
+    }
+
+    match synced {
+        Ok(true) => {
+            info!("Refreshed user: {}", target.name);
+            ctx.say(format!("Refreshed nickname and roles for '{}'.", target.name)).await?;
+        },
+        Ok(false) => {
+            ctx.say(format!("User '{}' is not linked.", target.name)).await?;
+        },
+        Err(e) => {
+            error!("Error refreshing user {}", e);
+            ctx.say(format!("Error when attempting to refresh '{}'.", target.name)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the author may manage the guild the command was used in.
+async fn is_guild_admin(ctx: PoiseContext<'_>) -> bool {
+
+    let Some(channel) = ctx.guild_channel().await else {
+        return false;
+    };
+
+    let Some(member) = ctx.author_member().await else {
+        return false;
+    };
+
+    let Some(guild) = ctx.guild() else {
+        return false;
+    };
+
+    guild.user_permissions_in(&channel, &member).manage_guild()
+}
+
+/// Whether the author may manage the guild or has its moderator role.
+async fn is_guild_moderator(ctx: PoiseContext<'_>) -> bool {
+
+    if is_guild_admin(ctx).await {
+        return true;
+    }
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return false;
+    };
+
+    let Ok(Some(role_id)) = ctx.data().config.database().fetch_moderator_role(guild_id.to_string()).await else {
+        return false;
+    };
+
+    let Some(member) = ctx.author_member().await else {
+        return false;
+    };
+
+    member.roles.iter().any(|role| role.to_string() == role_id)
+}
+
+/// Displays info about bot
+#[poise::command(prefix_command, track_edits, slash_command)]
+pub async fn status(
+    ctx: PoiseContext<'_>
+) -> Result<(), Error> {
+
     let http = ctx.http();
 
-    match DiscordBot::link_user(&username, http, author.id, Some(&ctx)).await {
-        Ok(success) => {
-            if success {
-                info!("Successfully linked user: {}", author.name);
-                ctx.say(format!("Successfully linked Discord user '{}' to Faceit account '{}'.", author.name, username)).await?;
-            } else {
-                error!("Error linking Discord user '{}' to Faceit account '{}'", author.name, username);
+    let Ok(user_count) = ctx.data().config.database().count_users().await else {
+        error!("Error counting users");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
+        error!("Error attempting to get guilds.");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    let mut message = format!("Connected to {} guilds. Total of {} users linked.", guilds.len(), user_count);
+
+    if let Some(guild_id) = ctx.guild_id() {
+        match ctx.data().config.database().fetch_sync_status(guild_id.to_string(), ctx.author().id.to_string()).await {
+            Ok(Some(status)) => {
+                message.push_str(&format!("\nYour last sync here was <t:{}:R>: {}", status.updated_at, describe_status(&status.status, status.detail.as_deref())));
+            },
+            Ok(None) => {},
+            Err(e) => error!("Error fetching sync status {}", e),
+        }
+    }
+
+    for (name, health) in ctx.data().supervisor.health() {
+        message.push_str(&format!("\n**Task**: '{}', **State**: '{}', **Restarts**: '{}'.", name, health.state.as_str(), health.restarts));
+    }
+
+    ctx.say(message).await?;
+
+    Ok(())
+}
+
+/// Lists members in this guild whose nickname or roles could not be synced
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn failing(
+    ctx: PoiseContext<'_>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let Ok(statuses) = ctx.data().config.database().fetch_failing_members(guild_id.to_string()).await else {
+        error!("Error fetching failing members");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    if statuses.is_empty() {
+        ctx.say("No failing members, everyone is in sync.").await?;
+        return Ok(());
+    }
+
+    let mut message = String::from("# Failing members \n");
+
+    // Keeps the reply below Discord's message length limit.
+    for status in statuses.iter().take(20) {
+        message.push_str(format!("<@{}> <t:{}:R>: {}\n", status.discord_id, status.updated_at, describe_status(&status.status, status.detail.as_deref())).as_str());
+    }
+
+    if statuses.len() > 20 {
+        message.push_str(format!("And {} more.\n", statuses.len() - 20).as_str());
+    }
+
+    ctx.say(message).await?;
+
+    Ok(())
+}
+
+/// Sets the channel where Faceit nickname changes are announced
+///
+/// Leave the channel out to turn announcements off.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn announcements(
+    ctx: PoiseContext<'_>,
+    #[description = "Announcement channel"] channel: Option<GuildChannel>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    // Channels are looked up by ID, which would also find channels of other guilds.
+    if channel.as_ref().is_some_and(|channel| channel.guild_id != guild_id) {
+        ctx.say("The channel has to be in this guild.").await?;
+        return Ok(());
+    }
+
+    let channel = channel.map(|channel| channel.id);
+
+    let Ok(_) = ctx.data().config.database().set_announce_channel(guild_id.to_string(), channel.map(|c| c.to_string())).await else {
+        error!("Error setting announcement channel");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    match channel {
+        Some(channel) => ctx.say(format!("Faceit nickname changes will be announced in <#{}>.", channel)).await?,
+        None => ctx.say("Faceit nickname changes will no longer be announced.").await?,
+    };
+
+    Ok(())
+}
+
+/// Sets the channel where link, unlink and admin actions affecting this guild are posted
+///
+/// Leave the channel out to stop posting them.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn modlog(
+    ctx: PoiseContext<'_>,
+    #[description = "Mod-log channel"] channel: Option<GuildChannel>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    // Channels are looked up by ID, which would also find channels of other guilds.
+    if channel.as_ref().is_some_and(|channel| channel.guild_id != guild_id) {
+        ctx.say("The channel has to be in this guild.").await?;
+        return Ok(());
+    }
+
+    let channel = channel.map(|channel| channel.id);
+
+    let Ok(_) = ctx.data().config.database().set_modlog_channel(guild_id.to_string(), channel.map(|c| c.to_string())).await else {
+        error!("Error setting mod-log channel");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    match channel {
+        Some(channel) => ctx.say(format!("Link, unlink and admin actions will be posted in <#{}>.", channel)).await?,
+        None => ctx.say("Link, unlink and admin actions will no longer be posted.").await?,
+    };
+
+    Ok(())
+}
+
+/// Sets what happens to members whose Faceit account is banned
+///
+/// 'removerank' takes away their rank role, 'role' gives them the banned role in its place.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn banpolicy(
+    ctx: PoiseContext<'_>,
+    #[description = "What to do with banned members"] policy: BanPolicy,
+    #[description = "Role for banned members, needed for 'role'"] role: Option<RoleId>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    if policy == BanPolicy::Role && role.is_none() {
+        ctx.say("Pick the role banned members should get.").await?;
+        return Ok(());
+    }
+
+    let Ok(_) = ctx.data().config.database().set_ban_policy(guild_id.to_string(), policy.name().to_string(), role.map(|r| r.to_string())).await else {
+        error!("Error setting ban policy");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    let message = match (policy, role) {
+        (BanPolicy::Role, Some(role)) => format!("Members banned on Faceit will get <@&{}> in place of their rank role.", role),
+        (BanPolicy::RemoveRank, _) => String::from("Members banned on Faceit will lose their rank role."),
+        _ => String::from("Members banned on Faceit will keep their rank role."),
+    };
+
+    ctx.say(format!("{} This applies from the next sync, use '/refresh guild' to update everyone now.", message)).await?;
+
+    Ok(())
+}
+
+/// Lists linked members in this guild whose Faceit account is banned
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn banned(
+    ctx: PoiseContext<'_>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let Ok(members) = ctx.data().config.database().fetch_banned_members(guild_id.to_string()).await else {
+        error!("Error fetching banned members");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    if members.is_empty() {
+        ctx.say("No linked members are banned on Faceit.").await?;
+        return Ok(());
+    }
+
+    let mut message = String::from("# Banned members \n");
+
+    // Keeps the reply below Discord's message length limit.
+    for member in members.iter().take(20) {
+        let until = match member.ends_at {
+            Some(ends_at) => format!("<t:{}:R>", ends_at),
+            None => String::from("'permanent'"),
+        };
+        message.push_str(format!("**User**: <@{}>, **Faceit**: '{}', **Reason**: '{}', **Ends**: {}.\n",
+                                 member.discord_id, member.nickname.as_deref().unwrap_or("unknown"),
+                                 member.reason.as_deref().unwrap_or("unknown"), until).as_str());
+    }
+
+    if members.len() > 20 {
+        message.push_str(format!("And {} more.\n", members.len() - 20).as_str());
+    }
+
+    ctx.say(message).await?;
+
+    Ok(())
+}
+
+/// Sets whether linked members get roles for their Faceit region and country
+///
+/// Region roles are created right away, country roles once a member from that country is synced.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn locationroles(
+    ctx: PoiseContext<'_>,
+    #[description = "Give roles such as 'Region EU'"] region: bool,
+    #[description = "Give country flag roles such as '🇸🇪 SE'"] country: bool
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    ctx.defer().await?;
+
+    let Ok(_) = ctx.data().config.database().set_location_roles(guild_id.to_string(), region, country).await else {
+        error!("Error setting location roles");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    if region {
+        let created = match ctx.http().get_guild(guild_id).await {
+            Ok(guild) => location::create_region_roles(ctx.http(), guild_id, &guild.roles).await,
+            Err(_) => false,
+        };
+        if !created {
+            ctx.say("Could not create the region roles, check that the bot can manage roles.").await?;
+            return Ok(());
+        }
+    }
+
+    let message = match (region, country) {
+        (true, true) => "Linked members will get region and country roles",
+        (true, false) => "Linked members will get region roles",
+        (false, true) => "Linked members will get country roles",
+        (false, false) => "Linked members will no longer get location roles, existing ones are left as they are",
+    };
+
+    ctx.say(format!("{} from the next sync. Use '/refresh guild' to update everyone now.", message)).await?;
+
+    Ok(())
+}
+
+/// Sets whether the top 1000 players of their Faceit region get the 'Faceit Challenger' role
+///
+/// They get it instead of the level 10 role. The role is created right away.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn challengerrole(
+    ctx: PoiseContext<'_>,
+    #[description = "Give the Challenger role"] enabled: bool
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    ctx.defer().await?;
+
+    let Ok(_) = ctx.data().config.database().set_challenger_role(guild_id.to_string(), enabled).await else {
+        error!("Error setting challenger role");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    if enabled {
+        let created = match ctx.http().get_guild(guild_id).await {
+            Ok(guild) => discord::create_challenger_role(ctx.http(), guild_id, &guild.roles).await,
+            Err(_) => false,
+        };
+        if !created {
+            ctx.say("Could not create the Challenger role, check that the bot can manage roles.").await?;
+            return Ok(());
+        }
+    }
+
+    let message = if enabled {
+        "Top ranked members will get the Challenger role instead of level 10"
+    } else {
+        "Top ranked members will get the level 10 role again"
+    };
+
+    ctx.say(format!("{} from the next sync. Use '/refresh guild' to update everyone now.", message)).await?;
+
+    Ok(())
+}
+
+/// Sets the role which can use the moderator commands in this guild
+///
+/// Leave the role out to only allow members with 'Manage Server'.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn moderatorrole(
+    ctx: PoiseContext<'_>,
+    #[description = "Moderator role"] role: Option<RoleId>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let Ok(_) = ctx.data().config.database().set_moderator_role(guild_id.to_string(), role.map(|r| r.to_string())).await else {
+        error!("Error setting moderator role");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    match role {
+        Some(role) => ctx.say(format!("Members with <@&{}> can now use the moderator commands.", role)).await?,
+        None => ctx.say("Only members with 'Manage Server' can now use the moderator commands.").await?,
+    };
+
+    Ok(())
+}
+
+/// Sets how nicknames of linked members look in this guild
+///
+/// Use '{elo}', '{level}', '{rank}' and '{nickname}' as placeholders, '{rank}' is the regional ranking position of level 10 players and has to stand on its own between spaces, not next to a separator like '|'. Leave the format out to go back to the default.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn nicknameformat(
+    ctx: PoiseContext<'_>,
+    #[description = "Nickname format, e.g. '({elo} ELO) {nickname}'"] format: Option<String>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let template = match &format {
+        Some(format) => match NicknameTemplate::parse(format) {
+            Ok(template) => template,
+            Err(reason) => {
+                ctx.say(reason).await?;
+                return Ok(());
+            }
+        },
+        None => NicknameTemplate::configured(ctx.data().config),
+    };
+
+    let Ok(_) = ctx.data().config.database().set_nickname_template(guild_id.to_string(), format).await else {
+        error!("Error setting nickname template");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    ctx.say(format!("Nicknames will look like '{}' from the next sync, the default is '{}'. Use '/refresh guild' to update everyone now.",
+                    template.as_str(), NicknameTemplate::configured(ctx.data().config).as_str())).await?;
+
+    Ok(())
+}
+
+/// Explains why nicknames and roles in this guild are not updating
+///
+/// Checks bot permissions and rank roles, and optionally a single member.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn diagnose(
+    ctx: PoiseContext<'_>,
+    #[description = "Member to check"] member: Option<User>
+) -> Result<(), Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    match DiscordBot::diagnose(ctx.data().config, ctx, guild_id, member.map(|member| member.id)).await {
+        Ok(report) => {
+            ctx.say(report).await?;
+        },
+        Err(e) => {
+            error!("Error diagnosing guild '{}': {}", guild_id, e);
+            ctx.say("Whops! Something went wrong.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the bot admins, who can use the commands for all guilds
+#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+pub async fn admins(
+    ctx: PoiseContext<'_>
+) -> Result<(), Error> {
+
+    let Ok(admins) = ctx.data().config.database().fetch_bot_admins().await else {
+        error!("Error fetching bot admins");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    let mut message = String::from("# Bot admins \n");
+
+    for owner in ctx.framework().options().owners.iter() {
+        message.push_str(format!("**User**: <@{}>, **Role**: 'owner'.\n", owner).as_str());
+    }
+
+    for (discord_id, added_by) in admins.iter() {
+        message.push_str(format!("**User**: <@{}>, **Added by**: <@{}>.\n", discord_id, added_by).as_str());
+    }
+
+    ctx.say(message).await?;
+
+    Ok(())
+}
+
+/// Lets a user run the commands for all guilds
+#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+pub async fn addadmin(
+    ctx: PoiseContext<'_>,
+    #[description = "User"] user: User
+) -> Result<(), Error> {
+
+    match ctx.data().config.database().add_bot_admin(user.id.to_string(), ctx.author().id.to_string()).await {
+        Ok(true) => {
+            info!("Added bot admin: {}", user.name);
+            audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::AddAdmin, discord_id: Some(user.id), faceit_id: None, guild_id: None }).await;
+            ctx.say(format!("'{}' is now a bot admin.", user.name)).await?;
+        },
+        Ok(false) => {
+            ctx.say(format!("'{}' is already a bot admin.", user.name)).await?;
+        },
+        Err(e) => {
+            error!("Error adding bot admin {}", e);
+            ctx.say("Whops! Something went wrong.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Takes away a user's access to the commands for all guilds
+#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+pub async fn removeadmin(
+    ctx: PoiseContext<'_>,
+    #[description = "User"] user: User
+) -> Result<(), Error> {
+
+    match ctx.data().config.database().remove_bot_admin(user.id.to_string()).await {
+        Ok(true) => {
+            info!("Removed bot admin: {}", user.name);
+            audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::RemoveAdmin, discord_id: Some(user.id), faceit_id: None, guild_id: None }).await;
+            ctx.say(format!("'{}' is no longer a bot admin.", user.name)).await?;
+        },
+        Ok(false) => {
+            ctx.say(format!("'{}' is not a bot admin.", user.name)).await?;
+        },
+        Err(e) => {
+            error!("Error removing bot admin {}", e);
+            ctx.say("Whops! Something went wrong.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A Discord user or Faceit account on the blocklist.
+enum BlockTarget {
+    Discord(UserId),
+    Faceit { id: String, nickname: String },
+}
+
+impl BlockTarget {
+
+    fn kind(&self) -> &'static str {
+        match self {
+            BlockTarget::Discord(_) => "discord",
+            BlockTarget::Faceit { .. } => "faceit",
+        }
+    }
+
+    fn id(&self) -> String {
+        match self {
+            BlockTarget::Discord(user_id) => user_id.to_string(),
+            BlockTarget::Faceit { id, .. } => id.clone(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            BlockTarget::Discord(user_id) => format!("<@{}>", user_id),
+            BlockTarget::Faceit { nickname, .. } => format!("Faceit account '{}'", nickname),
+        }
+    }
+
+    fn audit_event(&self, actor: UserId, action: AuditAction) -> AuditEvent {
+        match self {
+            BlockTarget::Discord(user_id) => AuditEvent { actor, action, discord_id: Some(*user_id), faceit_id: None, guild_id: None },
+            BlockTarget::Faceit { id, .. } => AuditEvent { actor, action, discord_id: None, faceit_id: Some(id.clone()), guild_id: None },
+        }
+    }
+
+    /// Linked Discord users whose nickname and roles depend on the target.
+    async fn affected_users(&self, config: &'static Config) -> Vec<UserId> {
+        match self {
+            BlockTarget::Discord(user_id) => vec![*user_id],
+            BlockTarget::Faceit { id, .. } => config.database().fetch_discord_ids(id.clone()).await.unwrap_or_default()
+                .iter()
+                .filter_map(|discord_id| discord_id.parse::<u64>().ok())
+                .map(UserId::new)
+                .collect(),
+        }
+    }
+
+}
+
+/// Exactly one of the user and the Faceit username has to be given, otherwise tells the author.
+async fn block_target(ctx: PoiseContext<'_>, user: Option<User>, faceit: Option<String>) -> Result<Option<BlockTarget>, Error> {
+
+    match (user, faceit) {
+        (Some(user), None) => Ok(Some(BlockTarget::Discord(user.id))),
+        (None, Some(faceit)) => {
+            let Some(player) = DiscordBot::resolve_player(ctx.data().config, &faceit).await? else {
+                ctx.say("Faceit account not found.").await?;
+                return Ok(None);
+            };
+            Ok(Some(BlockTarget::Faceit { id: player.player_id.to_string(), nickname: player.nickname.to_string() }))
+        },
+        _ => {
+            ctx.say("Give either a Discord user or a Faceit username.").await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Stops a Discord user or Faceit account from being linked, and strips the rank it gave
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
+pub async fn block(
+    ctx: PoiseContext<'_>,
+    #[description = "Discord user"] user: Option<User>,
+    #[description = "Faceit username"] faceit: Option<String>,
+    #[description = "Reason"] reason: Option<String>
+) -> Result<(), Error> {
+
+    let Some(target) = block_target(ctx, user, faceit).await? else {
+        return Ok(());
+    };
+
+    match ctx.data().config.database().add_block(target.kind(), target.id(), reason, ctx.author().id.to_string()).await {
+        Ok(true) => {
+            info!("Blocked {} '{}'", target.kind(), target.id());
+            audit::record(ctx.data().config, ctx.http(), target.audit_event(ctx.author().id, AuditAction::Block)).await;
+            // Cleared once here, the scheduler stops syncing blocked accounts.
+            for discord_id in target.affected_users(ctx.data().config).await {
+                DiscordBot::clear_blocked(ctx.data().config, ctx, discord_id).await;
+                ctx.data().scheduler.enqueue(discord_id).await;
+            }
+            ctx.say(format!("Blocked {}.", target.describe())).await?;
+        },
+        Ok(false) => {
+            ctx.say(format!("{} is already blocked.", target.describe())).await?;
+        },
+        Err(e) => {
+            error!("Error blocking {} '{}': {}", target.kind(), target.id(), e);
+            ctx.say("Whops! Something went wrong.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Allows a blocked Discord user or Faceit account to be linked again
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
+pub async fn unblock(
+    ctx: PoiseContext<'_>,
+    #[description = "Discord user"] user: Option<User>,
+    #[description = "Faceit username"] faceit: Option<String>
+) -> Result<(), Error> {
+
+    let Some(target) = block_target(ctx, user, faceit).await? else {
+        return Ok(());
+    };
+
+    match ctx.data().config.database().remove_block(target.kind(), target.id()).await {
+        Ok(true) => {
+            info!("Unblocked {} '{}'", target.kind(), target.id());
+            audit::record(ctx.data().config, ctx.http(), target.audit_event(ctx.author().id, AuditAction::Unblock)).await;
+            for discord_id in target.affected_users(ctx.data().config).await {
+                ctx.data().scheduler.enqueue(discord_id).await;
             }
+            ctx.say(format!("Unblocked {}.", target.describe())).await?;
+        },
+        Ok(false) => {
+            ctx.say(format!("{} is not blocked.", target.describe())).await?;
         },
         Err(e) => {
-            ctx.say(format!("Error when attempting to link Discord user '{}' to Faceit account '{}'.", author.name, username)).await?;
-            error!("Error linking user {}", e);
+            error!("Error unblocking {} '{}': {}", target.kind(), target.id(), e);
+            ctx.say("Whops! Something went wrong.").await?;
         }
     }
 
     Ok(())
 }
 
-/// Unlinks from Faceit account
-#[poise::command(prefix_command, track_edits, slash_command)]
-pub async fn unlink(
+/// Lists blocked Discord users and Faceit accounts
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
+pub async fn blocklist(
     ctx: PoiseContext<'_>
 ) -> Result<(), Error> {
 
-    let author = ctx.author();
-
-    let http = ctx.http();
-
-    let Ok(exists) = Database.user_exists(author.id.to_string()).await else {
-        error!("Error checking if user exists");
+    let Ok(entries) = ctx.data().config.database().fetch_blocklist().await else {
+        error!("Error fetching blocklist");
         ctx.say("Whops! Something went wrong.").await?;
-        return Ok(())
+        return Ok(());
     };
 
-    if !exists {
-        ctx.say("User not linked. Please link using '!link *faceitUsername*'").await?;
-        return Ok(())
-    };
+    if entries.is_empty() {
+        ctx.say("Nobody is blocked.").await?;
+        return Ok(());
+    }
 
-    let Ok(success) = Database.unlink_user(author.id.to_string()).await else {
-        ctx.say(format!("Error when attempting to unlink user '{}'.", author.name)).await?;
-        error!("Error unlinking user");
-        return Ok(())
-    };
+    let mut message = String::from("# Blocklist \n");
 
-    if success {
-        ctx.say(format!("Successfully unlinked user '{}'.", author.name)).await?;
-        info!("Attempting to clear nickname in all relevant guilds.");
-        DiscordBot::clear_user(http, author.id).await;
-    } else {
-        ctx.say(format!("Error when attempting to unlink user '{}'.", author.name)).await?;
-        error!("Error unlinking user");
+    // Keeps the reply below Discord's message length limit.
+    for entry in entries.iter().take(20) {
+        let target = match entry.kind.as_str() {
+            "discord" => format!("**User**: <@{}>", entry.id),
+            _ => format!("**Faceit ID**: '{}'", entry.id),
+        };
+        let added_by = match entry.added_by.as_str() {
+            FACEIT_BAN_BLOCKER => String::from("Faceit ban"),
+            added_by => format!("<@{}>", added_by),
+        };
+        message.push_str(format!("{}, **Reason**: '{}', **Added by**: {} <t:{}:R>.\n",
+                                 target, entry.reason.as_deref().unwrap_or("none"), added_by, entry.added_at).as_str());
+    }
+
+    if entries.len() > 20 {
+        message.push_str(format!("And {} more.\n", entries.len() - 20).as_str());
     }
 
+    ctx.say(message).await?;
+
     Ok(())
 }
 
-/// Displays info about bot
+/// Lists recent link, unlink and admin actions
+///
+/// Moderators only see actions in their guild, bot admins see everything.
 #[poise::command(prefix_command, track_edits, slash_command)]
-pub async fn status(
-    ctx: PoiseContext<'_>
+pub async fn audit(
+    ctx: PoiseContext<'_>,
+    #[description = "Only actions by this user"] actor: Option<User>,
+    #[description = "Only actions on this user"] user: Option<User>,
+    #[description = "Only this action"] action: Option<AuditAction>,
+    #[description = "Only this guild ID, bot admins only"] guild_id: Option<String>
 ) -> Result<(), Error> {
 
-    let http = ctx.http();
+    let guild_id = if is_owner_or_admin(ctx).await? {
+        guild_id
+    } else {
+        let Some(guild_id) = moderated_guild(ctx).await? else {
+            if ctx.guild_id().is_none() {
+                ctx.say("Only bot owners and admins can use this command outside of a guild.").await?;
+            }
+            return Ok(());
+        };
+        Some(guild_id.to_string())
+    };
 
-    let Ok(user_count) = Database.count_users().await else {
-        error!("Error counting users");
-        ctx.say("Whops! Something went wrong.").await?;
-        return Ok(());
+    let filter = AuditFilter {
+        actor: actor.map(|actor| actor.id.to_string()),
+        action: action.map(|action| action.name().to_string()),
+        discord_id: user.map(|user| user.id.to_string()),
+        guild_id,
     };
 
-    let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
-        error!("Error attempting to get guilds.");
+    // Keeps the reply below Discord's message length limit.
+    let Ok(entries) = ctx.data().config.database().fetch_audit_entries(filter, 20).await else {
+        error!("Error fetching audit entries");
         ctx.say("Whops! Something went wrong.").await?;
         return Ok(());
     };
 
-    ctx.say(format!("Connected to {} guilds. Total of {} users linked.", guilds.len(), user_count)).await?;
+    if entries.is_empty() {
+        ctx.say("No matching actions.").await?;
+        return Ok(());
+    }
+
+    let mut message = String::from("# Audit log \n");
+
+    for entry in entries.iter() {
+        message.push_str(format!("{}\n", audit::describe(entry)).as_str());
+    }
+
+    ctx.say(message).await?;
 
     Ok(())
 }
 
+/// Whether the author is a bot owner or admin, without replying.
+async fn is_owner_or_admin(ctx: PoiseContext<'_>) -> Result<bool, Error> {
+
+    let author = ctx.author().id;
+
+    if ctx.framework().options().owners.contains(&author) {
+        return Ok(true);
+    }
+
+    Ok(ctx.data().config.database().is_bot_admin(author.to_string()).await?)
+}
+
+/// Owners and bot admins can use the commands for all guilds.
+async fn is_bot_admin(ctx: PoiseContext<'_>) -> Result<bool, Error> {
+
+    if is_owner_or_admin(ctx).await? {
+        return Ok(true);
+    }
+
+    ctx.send(CreateReply::default().content("Only bot owners and admins can call this command").ephemeral(true)).await?;
+
+    Ok(false)
+}
+
 /// Displays info about guilds which bot is member of
-#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
 pub async fn guilds(
     ctx: PoiseContext<'_>
 ) -> Result<(), Error> {
@@ -145,7 +1167,7 @@ pub async fn guilds(
 }
 
 /// Removes bot from guild by ID
-#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
 pub async fn leave(
     ctx: PoiseContext<'_>,
     #[description = "Guild ID"] guild_id: String
@@ -167,6 +1189,7 @@ pub async fn leave(
     match guild.leave(http).await {
         Ok(_) => {
             info!("Left guild: '{}'", u64_id);
+            audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::Leave, discord_id: None, faceit_id: None, guild_id: Some(guild.id) }).await;
             ctx.say("Left guild.").await?;
         },
         _ => {
@@ -178,36 +1201,38 @@ pub async fn leave(
 }
 
 /// Force unlinks another user from a Faceit account
-#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
 pub async fn forceunlink(
     ctx: PoiseContext<'_>,
     #[description = "User ID (u64)"] user_id: String
 ) -> Result<(), Error> {
 
-    let http = ctx.http();
-
     let Ok(u64_id) = user_id.parse::<u64>() else {
         ctx.say("User ID not in valid format.").await?;
         return Ok(());
     };
 
-    let exists = Database.user_exists(user_id.clone()).await?;
+    let exists = ctx.data().config.database().user_exists(user_id.clone()).await?;
 
     if !exists {
         ctx.say("User not linked.").await?;
         return Ok(());
     }
 
-    let Ok(success) = Database.unlink_user(user_id.clone()).await else {
+    let faceit_id = primary_faceit_id(ctx.data().config, UserId::new(u64_id)).await;
+
+    let Ok(success) = ctx.data().config.database().unlink_user(user_id.clone()).await else {
         ctx.say(format!("Error when attempting to force unlink user '{}'.", u64_id)).await?;
         error!("Error force unlinking user");
         return Ok(());
     };
 
     if success {
+        audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::ForceUnlink, discord_id: Some(UserId::new(u64_id)), faceit_id, guild_id: None }).await;
+        ctx.data().scheduler.enqueue(UserId::new(u64_id)).await;
         ctx.say(format!("Successfully force unlinked user '{}'.", u64_id)).await?;
         info!("Attempting to clear nickname in all relevant guilds.");
-        DiscordBot::clear_user(http, UserId::new(u64_id)).await;
+        DiscordBot::clear_user(ctx.data().config, ctx, UserId::new(u64_id)).await;
     } else {
         ctx.say(format!("Error when attempting to force unlink user '{}'.", u64_id)).await?;
         error!("Error unlinking user");
@@ -217,24 +1242,25 @@ pub async fn forceunlink(
 }
 
 /// Force links another user to a Faceit account
-#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
 pub async fn forcelink(
     ctx: PoiseContext<'_>,
     #[description = "Faceit username"] username: String,
     #[description = "User ID (u64)"] user_id: String
 ) -> Result<(), Error> {
 
-    let http = ctx.http();
-
     let Ok(u64_id) = user_id.parse::<u64>() else {
         ctx.say("User ID not in valid format.").await?;
         return Ok(());
     };
 
-    match DiscordBot::link_user(&username, http, UserId::new(u64_id), Some(&ctx)).await {
+    match DiscordBot::link_user(ctx.data().config, &username, ctx, UserId::new(u64_id), Some(&ctx)).await {
         Ok(success) => {
             if success {
                 info!("Successfully force linked user: {}", u64_id);
+                let faceit_id = primary_faceit_id(ctx.data().config, UserId::new(u64_id)).await;
+                audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::ForceLink, discord_id: Some(UserId::new(u64_id)), faceit_id, guild_id: None }).await;
+                ctx.data().scheduler.enqueue(UserId::new(u64_id)).await;
                 ctx.say(format!("Successfully force linked Discord user '{}' to Faceit account '{}'.", user_id, username)).await?;
             } else {
                 error!("Error linking Discord user '{}' to Faceit account '{}'", u64_id, username);
@@ -250,87 +1276,396 @@ pub async fn forcelink(
 
 /// Restores links based on user nicknames
 ///
-/// Can be used if database is lost
-#[poise::command(prefix_command, track_edits, slash_command, owners_only)]
+/// Can be used if database is lost. Use 'dry_run' to review the proposed links before applying them.
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
 pub async fn restore(
     ctx: PoiseContext<'_>,
+    #[description = "Only restore this guild ID"] guild_id: Option<String>,
+    #[description = "Preview the links as a report before applying them"] #[flag] dry_run: bool
 ) -> Result<(), Error> {
 
     let http = ctx.http();
 
+    ctx.defer().await?;
+
     info!("Attempting to restore user links from member nicknames");
 
-    let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
-        ctx.say("Error attempting to get guilds.").await?;
-        return Ok(());
+    let guild_ids = match guild_id {
+        Some(guild_id) => {
+            let Ok(u64_id) = guild_id.parse::<u64>() else {
+                ctx.say("Guild ID not in valid format.").await?;
+                return Ok(());
+            };
+            vec![GuildId::new(u64_id)]
+        },
+        None => {
+            let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
+                ctx.say("Error attempting to get guilds.").await?;
+                return Ok(());
+            };
+            guilds.iter().map(|guild_info| guild_info.id).collect()
+        }
     };
 
-    let mut counter = 0;
-    let mut error_counter = 0;
-    let mut add_counter = 0;
-    let mut total_counter = 0;
+    let status = ctx.say("Starting restore...").await?;
 
-    let refresh_regex = Regex::new(r"\(\d+ ELO\)\s+([A-Za-z0-9-_]+)").unwrap();
+    let plan = plan_restore(ctx, &guild_ids, false, &status).await?;
 
-    for guild_info in guilds.iter() {
+    run_restore(ctx, plan, dry_run, &status).await
+}
 
-        let Ok(guild) = http.get_guild(guild_info.id).await else {
-            error!("Error attempting to get guild.");
-            continue;
-        };
+/// Scans the guilds using their nickname templates, keeping the status message up to date.
+async fn plan_restore(ctx: PoiseContext<'_>, guild_ids: &[GuildId], guild_scoped: bool, status: &ReplyHandle<'_>) -> Result<RestorePlan, Error> {
+
+    let mut plan = RestorePlan { guild_scoped, ..Default::default() };
+    let mut reported_at = Instant::now();
+
+    for (index, guild_id) in guild_ids.iter().enumerate() {
 
-        let Ok(members) = guild.members(http, None, None).await else {
-            error!("Could not get members from guild {}.", guild.name);
+        let Some((name, members)) = RestorePlan::guild_members(ctx.http(), *guild_id).await else {
             continue;
         };
 
-        for member in members.iter() {
+        let settings = ctx.data().config.database().fetch_guild_config(guild_id.to_string()).await?.unwrap_or_default();
+        let parser = NicknameTemplate::for_guild(ctx.data().config, &settings).regex();
 
-            total_counter += 1;
+        for (scanned, member) in members.iter().enumerate() {
 
-            let Some(nickname) = &member.nick else {
-                continue;
-            };
+            plan.scan_member(ctx.data().config, *guild_id, member, &parser).await;
 
-            let Some(caps) = refresh_regex.captures(nickname) else {
-                continue;
-            };
+            if reported_at.elapsed() >= RESTORE_PROGRESS_INTERVAL {
+                reported_at = Instant::now();
+                let progress = format!("Scanning guild '{}' ({}/{}): {}/{} members, {} links found.",
+                                       name, index + 1, guild_ids.len(), scanned + 1, members.len(), plan.proposals.len());
+                status.edit(ctx, CreateReply::default().content(progress)).await?;
+            }
+        }
+    }
 
-            let Some(username) = caps.get(1) else {
-                error!("Error getting username from regex capture");
-                continue;
-            };
+    plan.finish();
 
-            let parsed_username = username.as_str().to_string();
+    Ok(plan)
+}
 
-            counter += 1;
+/// Applies a restore plan, or turns the status message into a report with a button which applies it.
+async fn run_restore(ctx: PoiseContext<'_>, plan: RestorePlan, dry_run: bool, status: &ReplyHandle<'_>) -> Result<(), Error> {
 
-            if !nickname.ends_with(parsed_username.as_str()) {
-                error_counter += 1;
-                error!("Issue with regex found, missed '{}', captured: '{}'", nickname, parsed_username);
-                continue;
-            }
+    if !dry_run {
+        let summary = apply_restore(ctx, &plan).await;
+        status.edit(ctx, CreateReply::default().content(format!("Restore complete. {}", summary))).await?;
+        return Ok(());
+    }
 
-            let result = DiscordBot::link_user(&parsed_username, http, member.user.id, None).await;
+    let confirm_id = format!("restore-confirm-{}", ctx.id());
 
-            match result {
-                Ok(success) => {
-                    if success {
-                        add_counter += 1;
-                    }
-                }
-                Err(_) => {
-                    error_counter += 1;
-                    error!("Error when linking user '{}' to faceit account '{}'", member.user.name, parsed_username);
-                }
+    let summary = format!("Restore preview. Total: {}, Assumed: {}, Would link: {}, Conflicts: {}",
+                          plan.total, plan.proposals.len(), plan.applicable(), plan.conflicts());
+
+    let mut reply = CreateReply::default()
+        .content(summary.as_str())
+        .attachment(CreateAttachment::bytes(plan.to_csv().into_bytes(), "restore.csv"));
+
+    if plan.applicable() > 0 {
+        let button = CreateButton::new(confirm_id.as_str())
+            .label(format!("Apply {} links", plan.applicable()))
+            .style(ButtonStyle::Success);
+        reply = reply.components(vec![CreateActionRow::Buttons(vec![button])]);
+    }
+
+    status.edit(ctx, reply).await?;
+
+    if plan.applicable() == 0 {
+        return Ok(());
+    }
+
+    let interaction = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .timeout(RESTORE_CONFIRM_TIMEOUT)
+        .filter(move |interaction| interaction.data.custom_id == confirm_id)
+        .await;
+
+    let Some(interaction) = interaction else {
+        status.edit(ctx, CreateReply::default().content(format!("{}\nPreview expired, nothing was linked.", summary)).components(vec![])).await?;
+        return Ok(());
+    };
+
+    interaction.create_response(ctx, CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new().content(format!("{}\nApplying...", summary)).components(vec![])
+    )).await?;
+
+    let result = apply_restore(ctx, &plan).await;
+    ctx.say(format!("Restore complete. {}", result)).await?;
+
+    Ok(())
+}
+
+async fn apply_restore(ctx: PoiseContext<'_>, plan: &RestorePlan) -> RestoreSummary {
+
+    let (summary, linked) = plan.apply(ctx.data().config, ctx.author().id).await;
+
+    let mut per_guild: HashMap<GuildId, usize> = HashMap::new();
+
+    for proposal in linked {
+        audit::store(ctx.data().config, &AuditEvent {
+            actor: ctx.author().id,
+            action: AuditAction::Restore,
+            discord_id: Some(proposal.discord_id),
+            faceit_id: proposal.faceit_id.clone(),
+            guild_id: Some(proposal.guild_id),
+        }).await;
+        *per_guild.entry(proposal.guild_id).or_default() += 1;
+        ctx.data().scheduler.enqueue(proposal.discord_id).await;
+    }
+
+    // One message per guild instead of one per link, a restore can link hundreds of members.
+    for (guild_id, count) in per_guild {
+        let message = format!("<t:{}:f> <@{}> used 'restore', linking {} members from their nicknames. Use '/audit action:restore' for the list.",
+                              unix_now(), ctx.author().id, count);
+        audit::post(ctx.data().config, ctx.http(), guild_id, &message).await;
+    }
+
+    info!("Restore complete");
+
+    summary
+}
+
+/// Links a member of this guild to a Faceit account
+///
+/// The link only applies in this guild, where it is used instead of the member's own link.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only)]
+pub async fn modlink(
+    ctx: PoiseContext<'_>,
+    #[description = "Faceit username"] username: String,
+    #[description = "Member"] user: User
+) -> Result<(), Error> {
+
+    let Some(guild_id) = moderated_guild(ctx).await? else {
+        return Ok(());
+    };
+
+    if guild_id.member(ctx, user.id).await.is_err() {
+        ctx.say(format!("User '{}' is not a member of this guild.", user.name)).await?;
+        return Ok(());
+    }
+
+    match DiscordBot::link_member(ctx.data().config, &username, guild_id, user.id, ctx.author().id, &ctx).await {
+        Ok(Some(faceit_id)) => {
+            info!("Moderator '{}' linked user '{}' in guild '{}'", ctx.author().name, user.id, guild_id);
+            audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::ModLink, discord_id: Some(user.id), faceit_id: Some(faceit_id), guild_id: Some(guild_id) }).await;
+            ctx.data().scheduler.enqueue(user.id).await;
+            if let Err(e) = DiscordBot::sync_member(ctx.data().config, ctx, guild_id, user.id).await {
+                error!("Error syncing user '{}' in guild '{}': {}", user.id, guild_id, e);
             }
+            ctx.say(format!("Successfully linked '{}' to Faceit account '{}' in this guild.", user.name, username)).await?;
+        },
+        Ok(None) => {
+            info!("Moderator '{}' could not link user '{}' to Faceit account '{}'", ctx.author().name, user.id, username);
+        },
+        Err(e) => {
+            ctx.say(format!("Error when attempting to link '{}' to Faceit account '{}'.", user.name, username)).await?;
+            error!("Error linking user {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Unlinks a member in this guild
+///
+/// The member's own link is kept for other guilds, ask a bot admin to remove it with '!forceunlink'.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only)]
+pub async fn modunlink(
+    ctx: PoiseContext<'_>,
+    #[description = "Member"] user: User
+) -> Result<(), Error> {
+
+    let Some(guild_id) = moderated_guild(ctx).await? else {
+        return Ok(());
+    };
+
+    if guild_id.member(ctx, user.id).await.is_err() {
+        ctx.say(format!("User '{}' is not a member of this guild.", user.name)).await?;
+        return Ok(());
+    }
+
+    let Some(faceit_id) = DiscordBot::guild_account(ctx.data().config, guild_id, user.id).await? else {
+        ctx.say("User not linked.").await?;
+        return Ok(());
+    };
+
+    // The member's own link stays, it is only no longer applied in this guild.
+    let Ok(true) = ctx.data().config.database().set_guild_link(guild_id.to_string(), user.id.to_string(), None, ctx.author().id.to_string()).await else {
+        ctx.say(format!("Error when attempting to unlink '{}'.", user.name)).await?;
+        error!("Error unlinking user");
+        return Ok(());
+    };
+
+    info!("Moderator '{}' unlinked user '{}' in guild '{}'", ctx.author().name, user.id, guild_id);
+    audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::ModUnlink, discord_id: Some(user.id), faceit_id: Some(faceit_id), guild_id: Some(guild_id) }).await;
+    ctx.data().scheduler.enqueue(user.id).await;
+    ctx.say(format!("Successfully unlinked '{}' in this guild.", user.name)).await?;
+    DiscordBot::clear_member(ctx.data().config, ctx, guild_id, user.id).await;
+
+    Ok(())
+}
+
+/// Restores links based on nicknames of members in this guild
+///
+/// The links only apply in this guild. Use 'dry_run' to review the proposed links before applying them.
+#[poise::command(prefix_command, track_edits, slash_command, guild_only)]
+pub async fn modrestore(
+    ctx: PoiseContext<'_>,
+    #[description = "Preview the links as a report before applying them"] #[flag] dry_run: bool
+) -> Result<(), Error> {
+
+    ctx.defer().await?;
+
+    let Some(guild_id) = moderated_guild(ctx).await? else {
+        return Ok(());
+    };
+
+    info!("Attempting to restore user links from member nicknames in guild '{}'", guild_id);
+
+    let status = ctx.say("Starting restore...").await?;
+
+    let plan = plan_restore(ctx, &[guild_id], true, &status).await?;
+
+    run_restore(ctx, plan, dry_run, &status).await
+}
+
+/// Removes bot from this guild
+#[poise::command(prefix_command, track_edits, slash_command, guild_only)]
+pub async fn modleave(
+    ctx: PoiseContext<'_>,
+) -> Result<(), Error> {
+
+    let Some(guild_id) = moderated_guild(ctx).await? else {
+        return Ok(());
+    };
+
+    ctx.say("Leaving guild, bye!").await?;
 
+    match guild_id.leave(ctx.http()).await {
+        Ok(_) => {
+            info!("Moderator '{}' removed bot from guild '{}'", ctx.author().name, guild_id);
+            audit::record(ctx.data().config, ctx.http(), AuditEvent { actor: ctx.author().id, action: AuditAction::Leave, discord_id: None, faceit_id: None, guild_id: Some(guild_id) }).await;
+        },
+        Err(e) => {
+            error!("Error leaving guild '{}': {}", guild_id, e);
+            ctx.say("Error when leaving guild.").await?;
         }
+    }
+
+    Ok(())
+}
+
+/// The current guild if the author moderates it, otherwise tells them why not.
+async fn moderated_guild(ctx: PoiseContext<'_>) -> Result<Option<GuildId>, Error> {
+
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(None);
+    };
 
+    if !is_guild_moderator(ctx).await {
+        ctx.say("Only moderators of this guild can use this command.").await?;
+        return Ok(None);
     }
 
-    info!("Restore complete");
-    ctx.say(format!("Restore complete. Total: {}, Assumed: {}, Added: {}, Errors: {}", total_counter, counter, add_counter, error_counter)).await?;
+    Ok(Some(guild_id))
+}
+
+/// The Faceit ID driving the user's nickname, for audit entries.
+async fn primary_faceit_id(config: &'static Config, discord_id: UserId) -> Option<String> {
+    config.database().fetch_accounts(discord_id.to_string()).await.ok()?
+        .into_iter()
+        .find(|account| account.is_primary)
+        .map(|account| account.faceit_id)
+}
+
+/// Exports every link as a JSON or CSV backup
+///
+/// JSON backups also hold guild settings, moderator links and past nicknames, CSV backups only the links.
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
+pub async fn export(
+    ctx: PoiseContext<'_>,
+    #[description = "Backup format, JSON by default"] format: Option<BackupFormat>
+) -> Result<(), Error> {
+
+    let format = format.unwrap_or(BackupFormat::Json);
+
+    ctx.defer().await?;
+
+    let Ok(data) = backup::export(ctx.data().config.database(), format).await else {
+        error!("Error exporting links");
+        ctx.say("Whops! Something went wrong.").await?;
+        return Ok(());
+    };
+
+    let file_name = format!("links-{}.{}", unix_now(), format.extension());
+
+    ctx.send(CreateReply::default()
+        .content("Link backup.")
+        .attachment(CreateAttachment::bytes(data.into_bytes(), file_name))).await?;
+
+    Ok(())
+}
+
+/// Imports links from a JSON or CSV backup made with '/export'
+///
+/// Links which disagree with the database are skipped by default.
+#[poise::command(prefix_command, track_edits, slash_command, check = "is_bot_admin")]
+pub async fn import(
+    ctx: PoiseContext<'_>,
+    #[description = "Backup file"] file: Attachment,
+    #[description = "What to do with conflicting links, skip by default"] on_conflict: Option<ConflictPolicy>
+) -> Result<(), Error> {
+
+    let policy = on_conflict.unwrap_or(ConflictPolicy::Skip);
+
+    ctx.defer().await?;
+
+    let Ok(bytes) = file.download().await else {
+        ctx.say("Could not download the backup file.").await?;
+        return Ok(());
+    };
+
+    let backup = match backup::parse(&String::from_utf8_lossy(&bytes), BackupFormat::from_file_name(&file.filename)) {
+        Ok(backup) => backup,
+        Err(e) => {
+            ctx.say(format!("Could not read the backup file: {}", e)).await?;
+            return Ok(());
+        }
+    };
+
+    let report = match backup::import(ctx.data().config.database(), backup, policy).await {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Error importing links {}", e);
+            ctx.say("Whops! Something went wrong.").await?;
+            return Ok(());
+        }
+    };
+
+    let mut message = match policy {
+        ConflictPolicy::Report => format!("Import preview, nothing was written. {}", report),
+        _ => format!("Import complete. {}", report),
+    };
+
+    for conflict in report.conflicts.iter().take(20) {
+        message.push_str(format!("\n- {}", conflict).as_str());
+    }
+
+    if report.conflicts.len() > 20 {
+        message.push_str(format!("\n...and {} more.", report.conflicts.len() - 20).as_str());
+    }
+
+    // Have the scheduler pick up the imported links right away.
+    if policy != ConflictPolicy::Report {
+        ctx.data().scheduler.reload_now();
+    }
+
+    ctx.say(message).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
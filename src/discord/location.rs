@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serenity::all::{EditRole, GuildId, Http, PartialGuild, Role, RoleId};
+use tokio::time::sleep;
+use tracing::{error, info};
+use crate::database::GuildConfig;
+
+// Discord doesn't allow a guild more roles than this.
+const MAX_ROLES: usize = 250;
+
+/// Faceit regions and the role for each, created up front since there are only a few.
+pub(crate) const REGION_ROLES: &[(&str, &str)] = &[
+    ("EU", "Region EU"),
+    ("NA", "Region NA"),
+    ("SA", "Region SA"),
+    ("OCE", "Region OCE"),
+    ("SEA", "Region SEA"),
+];
+
+/// Which location roles a guild hands out, neither unless it opted in.
+#[derive(Default)]
+pub(crate) struct LocationRoles {
+    pub region: bool,
+    pub country: bool,
+}
+
+impl LocationRoles {
+
+    pub fn from_config(guild: &GuildConfig) -> Self {
+        LocationRoles { region: guild.region_roles, country: guild.country_roles }
+    }
+
+    /// Whether the bot adds and removes the role in this guild.
+    pub fn manages(&self, name: &str) -> bool {
+        (self.region && REGION_ROLES.iter().any(|(_, role)| *role == name)) || (self.country && is_country_role(name))
+    }
+
+    /// Names of the location roles a player with this region and country should have.
+    pub fn wanted(&self, region: Option<&str>, country: Option<&str>) -> Vec<String> {
+
+        let mut wanted = Vec::new();
+
+        if self.region {
+            if let Some(role) = region.and_then(region_role) {
+                wanted.push(role.to_string());
+            }
+        }
+
+        if self.country {
+            if let Some(role) = country.and_then(country_role) {
+                wanted.push(role);
+            }
+        }
+
+        wanted
+    }
+
+}
+
+fn region_role(region: &str) -> Option<&'static str> {
+    REGION_ROLES.iter().find(|(name, _)| name.eq_ignore_ascii_case(region)).map(|(_, role)| *role)
+}
+
+/// The flag followed by the code, such as '🇸🇪 SE', `None` for anything but a two letter code.
+pub(crate) fn country_role(code: &str) -> Option<String> {
+
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let code = code.to_ascii_uppercase();
+
+    // Flags are written as the two regional indicator symbols matching the letters of the code.
+    let flag: String = code.chars()
+        .filter_map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+        .collect();
+
+    Some(format!("{} {}", flag, code))
+}
+
+fn is_country_role(name: &str) -> bool {
+    name.rsplit(' ').next().and_then(country_role).is_some_and(|role| role == name)
+}
+
+/// Creates the region roles the guild is missing, returns false if any could not be created.
+pub(crate) async fn create_region_roles(http: &Http, guild_id: GuildId, roles: &HashMap<RoleId, Role>) -> bool {
+
+    for (_, role) in REGION_ROLES {
+
+        if roles.values().any(|existing| existing.name == *role) {
+            continue;
+        }
+
+        info!("Role '{}' not found, attempting to create!", role);
+
+        if let Err(e) = guild_id.create_role(http, EditRole::new().name(role.to_string()).mentionable(true)).await {
+            error!("Failed to create role in guild '{}' reason: {}", guild_id, e);
+            return false;
+        }
+
+        sleep(Duration::from_millis(40)).await;
+    }
+
+    true
+}
+
+/// Finds the role, creating it if the guild doesn't have it yet. Country roles are made on demand
+/// since a role for every country would not fit in Discord's role limit.
+///
+/// A created role is added to `guild`, so the rest of the pass finds it instead of creating it again.
+pub(crate) async fn find_or_create(http: &Http, guild: &mut PartialGuild, name: &str) -> Option<RoleId> {
+
+    if let Some(role) = guild.role_by_name(name) {
+        return Some(role.id);
+    }
+
+    if guild.roles.len() >= MAX_ROLES {
+        error!("Not creating role '{}' in guild '{}', it already has {} roles.", name, guild.name, MAX_ROLES);
+        return None;
+    }
+
+    info!("Role '{}' not found, attempting to create!", name);
+
+    match guild.id.create_role(http, EditRole::new().name(name).mentionable(true)).await {
+        Ok(role) => {
+            let role_id = role.id;
+            guild.roles.insert(role_id, role);
+            Some(role_id)
+        },
+        Err(e) => {
+            error!("Failed to create role in guild '{}' reason: {}", guild.name, e);
+            None
+        }
+    }
+}
@@ -1,24 +1,35 @@
-pub(crate) mod commands;
-
-use std::cell::RefCell;
-use std::collections::HashMap;
+pub mod commands;
+mod access;
+pub(crate) mod audit;
+pub(crate) mod bans;
+pub(crate) mod location;
+pub(crate) mod nickname;
+pub(crate) mod restore;
+
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
-use serenity::all::{Channel, Context, EditRole, ErrorResponse, EventHandler, Guild, GuildId, Http, Member, Message, PartialGuild, Ready, Role, RoleId, UnavailableGuild, User, UserId};
-use serenity::model::{guild, Colour};
-use serenity::{async_trait, http};
-use tokio::sync::Mutex;
-use std::sync::Arc;
-use std::{env, thread};
-use std::future::Future;
+use serenity::all::{CacheHttp, ChannelId, Context, EditRole, EventHandler, Guild, GuildId, Http, Member, PartialGuild, Ready, Role, RoleId, UnavailableGuild, UserId};
+use serenity::model::Colour;
+use serenity::async_trait;
 use anyhow::Error;
-use poise::Command;
+use regex::Regex;
 use tokio::time::sleep;
 use tracing::{error, info};
-use regex::Regex;
 use serenity::builder::EditMember;
-use crate::{Data, PoiseContext};
-use crate::database::Database;
-use crate::faceit::{Faceit, Player};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use crate::PoiseContext;
+use crate::config::Config;
+use crate::database::{unix_now, GuildConfig};
+use crate::faceit::Player;
+use crate::metrics;
+use crate::ratelimit::RateBudget;
+use crate::tasks::Supervisor;
+use access::GuildAccess;
+use audit::{AuditAction, AuditEvent};
+use bans::GuildBanConfig;
+use location::LocationRoles;
+use nickname::{Nickname, NicknameTemplate};
 
 const ALL_ROLES: &[&str] = &[
     "Level 1 (1-800 ELO)",
@@ -31,27 +42,154 @@ const ALL_ROLES: &[&str] = &[
     "Level 8 (1701-1850 ELO)",
     "Level 9 (1851-2000 ELO)",
     "Level 10 (2001+ ELO)",
+    CHALLENGER_ROLE,
 ];
 
-pub struct DiscordBot;
+// Given in place of the level 10 role to players this high up in their regional ranking, in guilds which opted in.
+// The name says where it comes from instead of taking over a guild's own 'Challenger' role.
+const CHALLENGER_ROLE: &str = "Faceit Challenger";
+const CHALLENGER_COLOUR: u32 = 0xFF5500;
+const CHALLENGER_POSITIONS: u64 = 1000;
+
+// How long a user has to put their verification code on Faceit.
+const CHALLENGE_TTL_SECS: i64 = 15 * 60;
+
+const BLOCKED_MESSAGE: &str = "This Discord user or Faceit account is blocked from linking.";
+
+/// Result of trying to bring a single member up to date in a guild.
+#[derive(Debug, PartialEq)]
+pub enum EditOutcome {
+    Edited,
+    Unchanged,
+    NotInGuild,
+    IsOwner,
+    MissingPermission,
+    HierarchyTooLow,
+    ApiError(String),
+}
+
+impl EditOutcome {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EditOutcome::Edited => "edited",
+            EditOutcome::Unchanged => "unchanged",
+            EditOutcome::NotInGuild => "not_in_guild",
+            EditOutcome::IsOwner => "is_owner",
+            EditOutcome::MissingPermission => "missing_permission",
+            EditOutcome::HierarchyTooLow => "hierarchy_too_low",
+            EditOutcome::ApiError(_) => "api_error",
+        }
+    }
+
+    pub fn detail(&self) -> Option<String> {
+        match self {
+            EditOutcome::ApiError(message) => Some(message.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, EditOutcome::Edited | EditOutcome::Unchanged | EditOutcome::NotInGuild)
+    }
+
+    pub fn describe(&self) -> String {
+        describe_status(self.as_str(), self.detail().as_deref())
+    }
+
+}
+
+/// Human readable form of a stored sync status.
+pub fn describe_status(status: &str, detail: Option<&str>) -> String {
+    match status {
+        "edited" => String::from("Nickname and roles were updated."),
+        "unchanged" => String::from("Nickname and roles were already up to date."),
+        "not_in_guild" => String::from("Not a member of the guild."),
+        "is_owner" => String::from("Discord does not allow bots to edit the guild owner."),
+        "missing_permission" => String::from("The bot can neither manage nicknames nor roles."),
+        "hierarchy_too_low" => String::from("The member's highest role is above the bot's highest role."),
+        "api_error" => format!("Discord returned an error: {}", detail.unwrap_or("unknown")),
+        other => format!("Unknown status '{}'.", other),
+    }
+}
+
+pub struct DiscordBot {
+    pub config: &'static Config,
+    /// Tracks the handlers below, so shutdown waits for them.
+    pub supervisor: Supervisor,
+}
+
+/// What `edit_member` does with the member's nickname.
+enum NicknameEdit<'a> {
+    /// Sets the nickname, an empty one resets it.
+    Set(&'a str),
+    /// Resets the nickname only if it was made from the guild's template, names members picked themselves are kept.
+    ResetTemplated(&'a Regex),
+}
+
+/// A guild's settings for synced members, loaded once per guild and sync pass instead of once per member.
+struct GuildSettings {
+    template: NicknameTemplate,
+    bans: GuildBanConfig,
+    locations: LocationRoles,
+    /// Top ranked players get the Challenger role instead of level 10.
+    challenger: bool,
+}
+
+impl GuildSettings {
+
+    async fn load(config: &'static Config, guild_id: GuildId) -> Self {
+
+        let guild = match config.database().fetch_guild_config(guild_id.to_string()).await {
+            Ok(guild) => guild.unwrap_or_default(),
+            Err(e) => {
+                error!("Could not fetch settings of guild '{}': {}", guild_id, e);
+                GuildConfig::default()
+            }
+        };
+
+        GuildSettings {
+            template: NicknameTemplate::for_guild(config, &guild),
+            bans: GuildBanConfig::from_config(&guild),
+            locations: LocationRoles::from_config(&guild),
+            challenger: guild.challenger_role,
+        }
+    }
+
+    /// Whether the guild uses the ranking position, which costs another Faceit request per level 10 player.
+    fn wants_rank(&self) -> bool {
+        self.challenger || self.template.uses_rank()
+    }
+
+}
+
+struct PlayerState {
+    blocked: bool,
+    /// The applied Faceit account has an active ban.
+    banned: bool,
+    /// Posted in the mod-log of every guild the account is applied in, when it was just found to be banned.
+    ban_notice: Option<String>,
+    region: Option<String>,
+    country: Option<String>,
+    /// Values for the nickname template and the level role name, missing if the player hasn't played CS2.
+    suggestion: Option<(Nickname, &'static str)>,
+}
 
 impl DiscordBot {
 
-    pub async fn link_user<T>(parsed_username: &String, http_t: T, discord_id: UserId, poise_ctx: Option<&PoiseContext<'_>>) -> Result<bool, Error>
+    pub async fn link_user<T>(config: &'static Config, parsed_username: &str, cache_http: T, discord_id: UserId, poise_ctx: Option<&PoiseContext<'_>>) -> Result<bool, Error>
     where
-        T: AsRef<Http>,
+        T: CacheHttp,
     {
 
-        let http: &Http = http_t.as_ref();
-
-        let Some(player_data) = Faceit::get_faceit_user_by_nickname(parsed_username.to_string()).await? else {
+        let Some(player_data) = Self::resolve_player(config, parsed_username).await? else {
             if let Some(px) = poise_ctx {
                 px.say("Faceit account not found.").await?;
             }
             return Ok(false);
         };
 
-        let exists = Database.user_exists(discord_id.to_string()).await?;
+        let exists = config.database().user_exists(discord_id.to_string()).await?;
 
         if exists {
             if let Some(px) = poise_ctx {
@@ -60,44 +198,255 @@ impl DiscordBot {
             return Ok(false);
         };
 
-        if let None = player_data.get_player_skill_level() {
+        if player_data.get_player_skill_level().is_none() {
             if let Some(px) = poise_ctx {
                 px.say("User has not played CS2 on Faceit.").await?;
             }
             return Ok(false);
         }
 
-        let success = Database.add_user(player_data.player_id.to_string(),discord_id.to_string()).await?;
+        if config.database().is_blocked(discord_id.to_string(), player_data.player_id.to_string()).await? {
+            if let Some(px) = poise_ctx {
+                px.say(BLOCKED_MESSAGE).await?;
+            }
+            return Ok(false);
+        }
 
-        Self::parse_user(http, discord_id, player_data).await;
+        let success = config.database().add_user(player_data.player_id.to_string(), discord_id.to_string(), Some(player_data.nickname.to_string()), true).await?;
+
+        Self::parse_user(config, &cache_http, discord_id, player_data).await;
 
         Ok(success)
     }
 
-    pub async fn clear_user<T>(http_t: T, discord_id: UserId)
+    /// Links a member to a Faceit account in a single guild, returning the Faceit ID it was linked to.
+    ///
+    /// Moderators can't prove the member owns the account, so the link never leaves their guild.
+    pub async fn link_member(config: &'static Config, parsed_username: &str, guild_id: GuildId, discord_id: UserId, linked_by: UserId, poise_ctx: &PoiseContext<'_>) -> Result<Option<String>, Error> {
+
+        let Some(player_data) = Self::resolve_player(config, parsed_username).await? else {
+            poise_ctx.say("Faceit account not found.").await?;
+            return Ok(None);
+        };
+
+        if Self::guild_account(config, guild_id, discord_id).await?.is_some() {
+            poise_ctx.say("User already linked in this guild, unlink using '!modunlink'.").await?;
+            return Ok(None);
+        }
+
+        if player_data.get_player_skill_level().is_none() {
+            poise_ctx.say("User has not played CS2 on Faceit.").await?;
+            return Ok(None);
+        }
+
+        if config.database().is_blocked(discord_id.to_string(), player_data.player_id.to_string()).await? {
+            poise_ctx.say(BLOCKED_MESSAGE).await?;
+            return Ok(None);
+        }
+
+        config.database().set_guild_link(guild_id.to_string(), discord_id.to_string(), Some(player_data.player_id.to_string()), linked_by.to_string()).await?;
+
+        Ok(Some(player_data.player_id))
+    }
+
+    /// The Faceit account a member is linked to in a guild, a moderator's link in the guild comes before their own.
+    pub async fn guild_account(config: &'static Config, guild_id: GuildId, discord_id: UserId) -> Result<Option<String>, Error> {
+
+        if let Some(link) = config.database().fetch_guild_link(guild_id.to_string(), discord_id.to_string()).await? {
+            return Ok(link.faceit_id);
+        }
+
+        let accounts = config.database().fetch_accounts(discord_id.to_string()).await?;
+
+        Ok(accounts.into_iter().find(|account| account.is_primary).map(|account| account.faceit_id))
+    }
+
+    /// Guilds whose moderators linked or unlinked the member, the member's own link is not applied there.
+    async fn moderated_guilds(config: &'static Config, discord_id: UserId) -> HashSet<GuildId> {
+        match config.database().fetch_member_guild_links(discord_id.to_string()).await {
+            Ok(links) => links.iter()
+                .filter_map(|link| link.guild_id.parse::<u64>().ok())
+                .map(GuildId::new)
+                .collect(),
+            Err(e) => {
+                error!("Could not fetch guild links of user '{}': {}", discord_id, e);
+                HashSet::new()
+            }
+        }
+    }
+
+    /// Applies the primary account of a Discord user, returns false if the user has no linked accounts.
+    pub async fn sync_primary<T>(config: &'static Config, cache_http: T, discord_id: UserId) -> Result<bool, Error>
+    where
+        T: CacheHttp,
+    {
+
+        let accounts = config.database().fetch_accounts(discord_id.to_string()).await?;
+
+        let Some(primary) = accounts.iter().find(|account| account.is_primary) else {
+            return Ok(false);
+        };
+
+        if let Some(player) = config.faceit().get_faceit_user_by_id(&primary.faceit_id).await? {
+            Self::parse_user(config, &cache_http, discord_id, player).await;
+        }
+
+        Ok(true)
+    }
+
+    /// Looks up a Faceit player by nickname, falling back to nicknames the player has used before.
+    pub async fn resolve_player(config: &'static Config, nickname: &str) -> Result<Option<Player>, Error> {
+
+        if let Some(player) = config.faceit().get_faceit_user_by_nickname(nickname.to_string()).await? {
+            return Ok(Some(player));
+        }
+
+        let Some(faceit_id) = config.database().find_faceit_id_by_past_nickname(nickname.to_string()).await? else {
+            return Ok(None);
+        };
+
+        info!("Faceit nickname '{}' resolved through nickname history to '{}'.", nickname, faceit_id);
+
+        config.faceit().get_faceit_user_by_id(&faceit_id).await
+    }
+
+    /// Starts the ownership check for a Faceit account, returning the code the user has to put in their Faceit nickname.
+    ///
+    /// Accounts which are already linked skip verification, and are only made primary if asked to.
+    pub async fn create_link_challenge<T>(config: &'static Config, parsed_username: &str, cache_http: T, discord_id: UserId, make_primary: bool, poise_ctx: &PoiseContext<'_>) -> Result<Option<String>, Error>
+    where
+        T: CacheHttp,
+    {
+
+        let Some(player_data) = config.faceit().get_faceit_user_by_nickname(parsed_username.to_string()).await? else {
+            poise_ctx.say("Faceit account not found.").await?;
+            return Ok(None);
+        };
+
+        if config.database().is_blocked(discord_id.to_string(), player_data.player_id.to_string()).await? {
+            poise_ctx.say(BLOCKED_MESSAGE).await?;
+            return Ok(None);
+        }
+
+        if config.database().account_linked(discord_id.to_string(), player_data.player_id.to_string()).await? {
+            if make_primary {
+                config.database().set_primary(discord_id.to_string(), player_data.player_id.to_string()).await?;
+                poise_ctx.say(format!("Faceit account '{}' is now your primary account.", player_data.nickname)).await?;
+                Self::parse_user(config, &cache_http, discord_id, player_data).await;
+            } else {
+                poise_ctx.say("Faceit account already linked, see '!accounts'.").await?;
+            }
+            return Ok(None);
+        };
+
+        if player_data.get_player_skill_level().is_none() {
+            poise_ctx.say("User has not played CS2 on Faceit.").await?;
+            return Ok(None);
+        }
+
+        let code: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(6)
+            .map(char::from)
+            .collect::<String>()
+            .to_uppercase();
+
+        config.database().add_challenge(discord_id.to_string(), player_data.player_id, code.clone(), unix_now() + CHALLENGE_TTL_SECS, make_primary).await?;
+
+        Ok(Some(code))
+    }
+
+    /// Completes a pending link if the challenge code is visible on the Faceit account.
+    pub async fn verify_link<T>(config: &'static Config, cache_http: T, discord_id: UserId, poise_ctx: &PoiseContext<'_>) -> Result<bool, Error>
+    where
+        T: CacheHttp,
+    {
+
+        let Some(challenge) = config.database().fetch_challenge(discord_id.to_string()).await? else {
+            poise_ctx.say("No pending link found, or it has expired. Start over using '!link *faceitUsername*'.").await?;
+            return Ok(false);
+        };
+
+        let Some(player_data) = config.faceit().get_faceit_user_by_id(&challenge.faceit_id).await? else {
+            poise_ctx.say("Faceit account not found.").await?;
+            return Ok(false);
+        };
+
+        if !player_data.nickname.to_uppercase().contains(&challenge.code) {
+            poise_ctx.say(format!("Code '{}' not found in Faceit nickname '{}'. Faceit may take a moment to update, try again within {} minutes.",
+                                  challenge.code, player_data.nickname, (challenge.expires_at - unix_now()).max(0) / 60 + 1)).await?;
+            return Ok(false);
+        }
+
+        if config.database().account_linked(discord_id.to_string(), player_data.player_id.to_string()).await? {
+            poise_ctx.say("Faceit account already linked, see '!accounts'.").await?;
+            return Ok(false);
+        };
+
+        // The account may have been blocked while the challenge was pending.
+        if config.database().is_blocked(discord_id.to_string(), player_data.player_id.to_string()).await? {
+            poise_ctx.say(BLOCKED_MESSAGE).await?;
+            return Ok(false);
+        }
+
+        let success = config.database().add_user(player_data.player_id.to_string(), discord_id.to_string(), Some(player_data.nickname.to_string()), challenge.make_primary).await?;
+
+        // The nickname still holds the code, so the syncer records the next one without announcing a rename.
+        config.database().set_link_code(player_data.player_id.to_string(), discord_id.to_string(), challenge.code).await?;
+
+        config.database().remove_challenge(discord_id.to_string()).await?;
+
+        audit::record(config, cache_http.http(), AuditEvent { actor: discord_id, action: AuditAction::Link, discord_id: Some(discord_id), faceit_id: Some(player_data.player_id.clone()), guild_id: None }).await;
+
+        Self::sync_primary(config, &cache_http, discord_id).await?;
+
+        Ok(success)
+    }
+
+    /// Posts a Faceit rename in the announcement channel of every guild the user is a member of.
+    pub async fn announce_rename<T>(config: &'static Config, http_t: T, user_id: UserId, old_nickname: &str, new_nickname: &str)
+    where
+        T: AsRef<Http>,
+    {
+        let message = format!("<@{}> changed their Faceit nickname from '{}' to '{}'.", user_id, old_nickname, new_nickname);
+        Self::announce(config, http_t, user_id, message).await;
+    }
+
+    /// Posts the ELO change from a finished match in the announcement channels of the user's guilds.
+    pub async fn announce_match<T>(config: &'static Config, http_t: T, user_id: UserId, nickname: &str, old_elo: &str, new_elo: &str)
+    where
+        T: AsRef<Http>,
+    {
+        let message = format!("<@{}> finished a match as '{}': {} -> {} ELO.", user_id, nickname, old_elo, new_elo);
+        Self::announce(config, http_t, user_id, message).await;
+    }
+
+    async fn announce<T>(config: &'static Config, http_t: T, user_id: UserId, message: String)
     where
         T: AsRef<Http>,
     {
 
         let http: &Http = http_t.as_ref();
 
-        let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
-            error!("Error attempting to get guilds.");
+        let Ok(channels) = config.database().fetch_announce_channels().await else {
+            error!("Error attempting to get announcement channels.");
             return;
         };
 
-        for guild_info in guilds.iter() {
+        for (guild_id, channel_id) in channels.iter() {
 
-            let Ok(guild) = http.get_guild(guild_info.id).await else {
-                error!("Error attempting to get guild.");
+            let (Ok(guild_id), Ok(channel_id)) = (guild_id.parse::<u64>(), channel_id.parse::<u64>()) else {
                 continue;
             };
 
-            let success = Self::edit_member(&http, &guild, discord_id, "", None).await;
-            if success {
-                //info!("Edited user in guild {} successfully.", guild.name);
-            } else {
-                error!("Error attempting to edit user in guild {}.", guild.name);
+            config.discord_budget.acquire().await;
+
+            if http.get_member(GuildId::new(guild_id), user_id).await.is_err() {
+                continue;
+            }
+
+            if let Err(e) = ChannelId::new(channel_id).say(http, &message).await {
+                error!("Error posting announcement in guild '{}': {}", guild_id, e);
             }
 
             sleep(Duration::from_millis(30)).await;
@@ -106,25 +455,67 @@ impl DiscordBot {
 
     }
 
-    pub async fn parse_user<T>(http_t: T, user_id: UserId, player: Player)
+    pub async fn clear_user<T>(config: &'static Config, cache_http: T, discord_id: UserId)
     where
-        T: AsRef<Http>,
+        T: CacheHttp,
     {
 
-        let http: &Http = http_t.as_ref();
+        let http: &Http = cache_http.http();
 
-        // These might get triggered if user hasn't played cs2.
-        let Some(level) = player.get_player_skill_level() else {
-            error!("Unlinked user {} {:#?}", user_id, player);
+        config.discord_budget.acquire().await;
+
+        let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
+            error!("Error attempting to get guilds.");
             return;
         };
-        let Some(elo) = player.get_player_elo() else {
+
+        let moderated = Self::moderated_guilds(config, discord_id).await;
+
+        for guild_info in guilds.iter().filter(|guild_info| !moderated.contains(&guild_info.id)) {
+
+            config.discord_budget.acquire().await;
+
+            let Ok(guild) = http.get_guild(guild_info.id).await else {
+                error!("Error attempting to get guild.");
+                continue;
+            };
+
+            let settings = GuildSettings::load(config, guild.id).await;
+
+            Self::clear_in_guild(config, &cache_http, &guild, &settings, discord_id).await;
+
+        }
+
+    }
+
+    /// Same as `clear_user`, but only touches a single guild.
+    pub async fn clear_member<T>(config: &'static Config, cache_http: T, guild_id: GuildId, discord_id: UserId)
+    where
+        T: CacheHttp,
+    {
+
+        config.discord_budget.acquire().await;
+
+        let Ok(guild) = cache_http.http().get_guild(guild_id).await else {
+            error!("Error attempting to get guild.");
             return;
         };
 
-        let suggested_name: &str = &format!("({} ELO) {}", elo, player.nickname);
+        let settings = GuildSettings::load(config, guild.id).await;
+
+        Self::clear_in_guild(config, &cache_http, &guild, &settings, discord_id).await;
 
-        let suggested_role: &str = ALL_ROLES.get(level - 1).unwrap_or(&"");
+    }
+
+    /// Clears a member who was just blocked in every guild where the account applied to them is blocked.
+    pub async fn clear_blocked<T>(config: &'static Config, cache_http: T, discord_id: UserId)
+    where
+        T: CacheHttp,
+    {
+
+        let http: &Http = cache_http.http();
+
+        config.discord_budget.acquire().await;
 
         let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
             error!("Error attempting to get guilds.");
@@ -133,123 +524,597 @@ impl DiscordBot {
 
         for guild_info in guilds.iter() {
 
+            match config.database().fetch_sync_status(guild_info.id.to_string(), discord_id.to_string()).await {
+                Ok(Some(_)) => {},
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Could not fetch sync status for member '{}' in guild '{}': {}", discord_id, guild_info.name, e);
+                    continue;
+                }
+            }
+
+            let faceit_id = match Self::guild_account(config, guild_info.id, discord_id).await {
+                Ok(faceit_id) => faceit_id.unwrap_or_default(),
+                Err(e) => {
+                    error!("Could not fetch account of user '{}' in guild '{}': {}", discord_id, guild_info.name, e);
+                    continue;
+                }
+            };
+
+            match config.database().is_blocked(discord_id.to_string(), faceit_id).await {
+                Ok(true) => {},
+                Ok(false) => continue,
+                Err(e) => {
+                    error!("Could not check blocklist for user '{}': {}", discord_id, e);
+                    continue;
+                }
+            }
+
+            config.discord_budget.acquire().await;
+
             let Ok(guild) = http.get_guild(guild_info.id).await else {
                 error!("Error attempting to get guild.");
                 continue;
             };
 
+            let settings = GuildSettings::load(config, guild.id).await;
+
+            Self::clear_in_guild(config, &cache_http, &guild, &settings, discord_id).await;
+
+        }
+
+    }
+
+    /// Resets the nickname the bot gave a member and removes the roles it manages.
+    async fn clear_in_guild<T>(config: &'static Config, cache_http: &T, guild: &PartialGuild, settings: &GuildSettings, discord_id: UserId)
+    where
+        T: CacheHttp,
+    {
+
+        let Some(access) = GuildAccess::resolve(cache_http, guild).await else {
+            error!("Could not resolve permissions in guild {}.", guild.name);
+            return;
+        };
+
+        let managed = Self::managed_roles(guild, &settings.bans, &settings.locations);
+        let templated = settings.template.regex();
+
+        let outcome = Self::edit_member(cache_http, &config.discord_budget, guild, &access, discord_id, NicknameEdit::ResetTemplated(&templated), &[], &managed).await;
+        metrics::DISCORD_EDITS.inc(outcome.as_str());
+        if outcome.is_failure() {
+            error!("Error attempting to clear user in guild {}: {}", guild.name, outcome.describe());
+        }
+
+        if let Err(e) = config.database().remove_sync_status(guild.id.to_string(), discord_id.to_string()).await {
+            error!("Could not remove sync status for member '{}' in guild '{}': {}", discord_id, guild.name, e);
+        }
+
+    }
+
+    /// Looked up once per user and shared by every guild they are synced in, the ban status is refreshed on the way.
+    /// The ranking position is only looked up if one of the guilds `wants_rank`.
+    async fn player_state(config: &'static Config, user_id: UserId, player: &Player, wants_rank: bool) -> PlayerState {
+
+        // Refreshed first, a new ban can block the account.
+        let ban_notice = bans::refresh(config, user_id, player).await;
+
+        let blocked = match config.database().is_blocked(user_id.to_string(), player.player_id.to_string()).await {
+            Ok(blocked) => blocked,
+            Err(e) => {
+                error!("Could not check blocklist for user '{}': {}", user_id, e);
+                false
+            }
+        };
+
+        let region = player.get_player_region();
+
+        let rank = match (&region, player.get_player_skill_level()) {
+            (Some(region), Some(10)) if wants_rank => config.faceit().get_player_ranking(region, &player.player_id).await.unwrap_or_else(|e| {
+                error!("Could not fetch ranking of Faceit user '{}': {}", player.player_id, e);
+                None
+            }),
+            _ => None,
+        };
+
+        PlayerState {
+            blocked,
+            banned: bans::is_banned(config, player).await,
+            ban_notice,
+            region,
+            country: player.country.clone(),
+            suggestion: Self::suggest(user_id, player, rank),
+        }
+    }
+
+    /// Brings a member up to date in one guild, blocked users keep their link but lose the nickname and roles it gave them.
+    async fn apply_player<T>(config: &'static Config, cache_http: &T, guild: &mut PartialGuild, settings: &GuildSettings, user_id: UserId, state: &PlayerState)
+    where
+        T: CacheHttp,
+    {
+
+        if state.blocked {
+            // Clearing removes the sync status, so a blocked member is only cleared once.
+            match config.database().fetch_sync_status(guild.id.to_string(), user_id.to_string()).await {
+                Ok(Some(_)) => Self::clear_in_guild(config, cache_http, guild, settings, user_id).await,
+                Ok(None) => {},
+                Err(e) => error!("Could not fetch sync status for member '{}' in guild '{}': {}", user_id, guild.name, e),
+            }
+            return;
+        }
+
+        Self::apply_to_guild(config, cache_http, guild, settings, user_id, state).await;
+
+        // Posted once the member has a sync status in the guild, which shows they are a member.
+        if let Some(notice) = &state.ban_notice {
+            match config.database().fetch_sync_status(guild.id.to_string(), user_id.to_string()).await {
+                Ok(Some(_)) => audit::post(config, cache_http.http(), guild.id, notice).await,
+                Ok(None) => {},
+                Err(e) => error!("Could not fetch sync status for member '{}' in guild '{}': {}", user_id, guild.name, e),
+            }
+        }
+    }
+
+    pub async fn parse_user<T>(config: &'static Config, cache_http: T, user_id: UserId, player: Player)
+    where
+        T: CacheHttp,
+    {
+
+        let http: &Http = cache_http.http();
+
+        config.discord_budget.acquire().await;
+
+        let Ok(guilds) = http.get_guilds(None, Some(100)).await else {
+            error!("Error attempting to get guilds.");
+            return;
+        };
+
+        let moderated = Self::moderated_guilds(config, user_id).await;
+
+        let mut applied = Vec::new();
+
+        for guild_info in guilds.iter().filter(|guild_info| !moderated.contains(&guild_info.id)) {
+            applied.push((guild_info.id, GuildSettings::load(config, guild_info.id).await));
+        }
+
+        let wants_rank = applied.iter().any(|(_, settings)| settings.wants_rank());
+
+        let state = Self::player_state(config, user_id, &player, wants_rank).await;
+
+        if state.blocked {
+            info!("User '{}' is blocked, clearing nickname and roles.", user_id);
+        }
+
+        for (guild_id, settings) in applied {
+
+            config.discord_budget.acquire().await;
+
+            let Ok(mut guild) = http.get_guild(guild_id).await else {
+                error!("Error attempting to get guild.");
+                continue;
+            };
+
             //info!("Attempting to edit user in guild {}.", guild.name);
 
-            let success;
+            Self::apply_player(config, &cache_http, &mut guild, &settings, user_id, &state).await;
 
-            match guild.role_by_name(suggested_role) {
-                None => {
-                    success = Self::edit_member(&http, &guild, user_id, suggested_name, None).await;
-                }
-                Some(role) => {
-                    success = Self::edit_member(&http, &guild, user_id, suggested_name, Some(role.id)).await;
+        }
+
+    }
+
+    /// Same as `parse_user`, but only touches a single guild.
+    pub async fn parse_user_in_guild<T>(config: &'static Config, cache_http: T, guild_id: GuildId, user_id: UserId, player: Player)
+    where
+        T: CacheHttp,
+    {
+
+        let http: &Http = cache_http.http();
+
+        config.discord_budget.acquire().await;
+
+        let Ok(mut guild) = http.get_guild(guild_id).await else {
+            error!("Error attempting to get guild.");
+            return;
+        };
+
+        let settings = GuildSettings::load(config, guild.id).await;
+        let state = Self::player_state(config, user_id, &player, settings.wants_rank()).await;
+
+        Self::apply_player(config, &cache_http, &mut guild, &settings, user_id, &state).await;
+
+    }
+
+    /// Values for the nickname template and level role name for a player.
+    fn suggest(user_id: UserId, player: &Player, rank: Option<u64>) -> Option<(Nickname, &'static str)> {
+
+        // These might get triggered if user hasn't played cs2.
+        let Some(level) = player.get_player_skill_level() else {
+            error!("Unlinked user {} {:#?}", user_id, player);
+            return None;
+        };
+        let elo = player.get_player_elo()?;
+
+        let suggested_name = Nickname { elo, level, rank, faceit_nickname: player.nickname.clone() };
+
+        let suggested_role: &'static str = ALL_ROLES.get(level - 1).unwrap_or(&"");
+
+        Some((suggested_name, suggested_role))
+    }
+
+    async fn apply_to_guild<T>(config: &'static Config, cache_http: &T, guild: &mut PartialGuild, settings: &GuildSettings, user_id: UserId, state: &PlayerState)
+    where
+        T: CacheHttp,
+    {
+
+        let Some((nickname, level_role)) = state.suggestion.as_ref().map(|(nickname, role)| (nickname, *role)) else {
+            return;
+        };
+
+        let challenger = settings.challenger && nickname.rank.is_some_and(|rank| rank <= CHALLENGER_POSITIONS);
+
+        let suggested_name = settings.template.render(nickname);
+
+        let Some(access) = GuildAccess::resolve(cache_http, guild).await else {
+            error!("Could not resolve permissions in guild {}.", guild.name);
+            return;
+        };
+
+        let problems = access.problems(guild);
+
+        if !problems.is_empty() {
+            access::report_problems(cache_http.http(), guild, &problems).await;
+        }
+
+        // Guilds missing the Challenger role keep giving the level 10 role.
+        let rank_role = challenger.then(|| guild.role_by_name(CHALLENGER_ROLE)).flatten()
+            .or_else(|| guild.role_by_name(level_role))
+            .map(|role| role.id);
+
+        let mut wanted: Vec<RoleId> = settings.bans.role_for(state.banned, rank_role).into_iter().collect();
+
+        // Created roles are added to `guild`, so they count as managed and assignable right away.
+        if access.manage_roles {
+            for name in settings.locations.wanted(state.region.as_deref(), state.country.as_deref()) {
+                if let Some(role) = location::find_or_create(cache_http.http(), guild, &name).await {
+                    wanted.push(role);
                 }
             }
+        }
 
-            if success {
-                //info!("Renamed user in guild {} successfully.", guild.name);
-            } else {
-                error!("Error attempting to edit user in guild {}.", guild.name);
+        let managed = Self::managed_roles(guild, &settings.bans, &settings.locations);
+
+        let outcome = Self::edit_member(cache_http, &config.discord_budget, guild, &access, user_id, NicknameEdit::Set(&suggested_name), &wanted, &managed).await;
+
+        metrics::DISCORD_EDITS.inc(outcome.as_str());
+
+        Self::record_outcome(config, guild, user_id, &outcome).await;
+
+    }
+
+    /// Roles in the guild which the bot adds and removes, any other role of a member is left alone.
+    fn managed_roles(guild: &PartialGuild, bans: &GuildBanConfig, locations: &LocationRoles) -> HashSet<RoleId> {
+        guild.roles.iter()
+            .filter(|(id, role)| ALL_ROLES.contains(&role.name.as_str()) || bans.role == Some(**id) || locations.manages(&role.name))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Stores the outcome of an edit, members who aren't in the guild have no row since `audit::notify` goes by them.
+    async fn record_outcome(config: &'static Config, guild: &PartialGuild, user_id: UserId, outcome: &EditOutcome) {
+
+        if *outcome == EditOutcome::NotInGuild {
+            if let Err(e) = config.database().remove_sync_status(guild.id.to_string(), user_id.to_string()).await {
+                error!("Could not remove sync status for member '{}' in guild '{}': {}", user_id, guild.name, e);
             }
+            return;
+        }
 
-            sleep(Duration::from_millis(30)).await;
+        if outcome.is_failure() {
+            info!("Could not sync member '{}' in guild '{}': {}", user_id, guild.name, outcome.describe());
+        }
 
+        if let Err(e) = config.database().record_sync_status(guild.id.to_string(), user_id.to_string(), outcome.as_str().to_string(), outcome.detail()).await {
+            error!("Could not store sync status for member '{}' in guild '{}': {}", user_id, guild.name, e);
         }
 
     }
 
-    async fn edit_member<T>(http_t: T, guild: &PartialGuild, member_id: UserId, new_name: &str, role: Option<RoleId>) -> bool
+    /// Reports permission and role hierarchy problems to the guild owner.
+    pub async fn check_guild<T>(cache_http: T, guild_id: GuildId)
     where
-        T: AsRef<Http>,
+        T: CacheHttp,
     {
 
-        let http: &Http = http_t.as_ref();
+        let Ok(guild) = cache_http.http().get_guild(guild_id).await else {
+            error!("Error attempting to get guild.");
+            return;
+        };
 
-        // @TODO: Make this return a result. Result<bool, Error>. As "false" should be returned
-        // if no edits were made. But now true is returned if no edits were made but user wasn't in guild.
-        // Which works. But its rather ugly.
+        let Some(access) = GuildAccess::resolve(&cache_http, &guild).await else {
+            error!("Could not resolve permissions in guild {}.", guild.name);
+            return;
+        };
 
-        let Ok(target_member) = guild.member(&http, &member_id).await else {
-            //info!("User not in guild {}.", guild.name);
-            return true;
+        let problems = access.problems(&guild);
+
+        if !problems.is_empty() {
+            access::report_problems(cache_http.http(), &guild, &problems).await;
+        }
+
+    }
+
+    /// Builds a report explaining why a guild, or a member in it, might not be syncing.
+    pub async fn diagnose<T>(config: &'static Config, cache_http: T, guild_id: GuildId, member_id: Option<UserId>) -> Result<String, Error>
+    where
+        T: CacheHttp,
+    {
+
+        let http: &Http = cache_http.http();
+
+        let guild = http.get_guild(guild_id).await?;
+
+        let Some(access) = GuildAccess::resolve(&cache_http, &guild).await else {
+            return Err(anyhow::anyhow!("Could not resolve permissions in guild {}", guild.name));
+        };
+
+        let mut report = format!("# Diagnosis for {} \n## Guild \n", guild.name);
+
+        let check = |ok: bool| if ok { "OK" } else { "Problem" };
+
+        report.push_str(&format!("**Manage Nicknames**: {}.\n", check(access.manage_nicknames)));
+        report.push_str(&format!("**Manage Roles**: {}.\n", check(access.manage_roles)));
+
+        let challenger = GuildSettings::load(config, guild_id).await.challenger;
+
+        let missing: Vec<&str> = ALL_ROLES.iter()
+            .filter(|name| challenger || **name != CHALLENGER_ROLE)
+            .filter(|name| guild.role_by_name(name).is_none())
+            .copied()
+            .collect();
+
+        if missing.is_empty() {
+            report.push_str("**Rank roles exist**: OK.\n");
+        } else {
+            report.push_str(&format!("**Rank roles exist**: Problem, missing {}.\n", missing.join(", ")));
+        }
+
+        let misplaced: Vec<&str> = ALL_ROLES.iter()
+            .filter(|name| guild.role_by_name(name).is_some_and(|role| !access.can_assign(&guild, role.id)))
+            .copied()
+            .collect();
+
+        if misplaced.is_empty() {
+            report.push_str("**Rank roles below bot role**: OK.\n");
+        } else {
+            report.push_str(&format!("**Rank roles below bot role**: Problem, move {} below the bot's role.\n", misplaced.join(", ")));
+        }
+
+        let Some(member_id) = member_id else {
+            return Ok(report);
+        };
+
+        report.push_str(&format!("## Member <@{}> \n", member_id));
+
+        match guild.member(http, member_id).await {
+            Ok(member) => {
+                let editable = guild.owner_id != member_id && access.can_edit_member(&guild, &member);
+                report.push_str(&format!("**Editable by bot**: {}.\n", check(editable)));
+            },
+            Err(_) => {
+                report.push_str("**In guild**: Problem, not a member of this guild.\n");
+            }
+        }
+
+        match config.database().fetch_sync_status(guild_id.to_string(), member_id.to_string()).await? {
+            Some(status) => report.push_str(&format!("**Last sync**: <t:{}:R>, {}\n", status.updated_at, describe_status(&status.status, status.detail.as_deref()))),
+            None => report.push_str("**Last sync**: Never.\n"),
+        }
+
+        let Some(faceit_id) = Self::guild_account(config, guild_id, member_id).await? else {
+            report.push_str("**Linked**: Problem, not linked to a Faceit account in this guild.\n");
+            return Ok(report);
         };
 
-        /*
+        report.push_str(&format!("**Linked**: OK, account '{}'.\n", faceit_id));
+
+        match config.faceit().get_faceit_user_by_id(&faceit_id).await? {
+            Some(player) => match (player.get_player_skill_level(), player.get_player_elo()) {
+                (Some(level), Some(elo)) => report.push_str(&format!("**Faceit CS2 data**: OK, level {} with {} ELO.\n", level, elo)),
+                _ => report.push_str("**Faceit CS2 data**: Problem, no CS2 data on Faceit.\n"),
+            },
+            None => report.push_str("**Faceit CS2 data**: Problem, Faceit account not found.\n"),
+        }
+
+        Ok(report)
+    }
 
-        @TODO: Permission check wont work with a partial guild. Find out how to do this.
-        You can access channels using a partial guild using guild.channels
-        This returns a hashmap, I could get a channel ID from there.
-        But that feels so clunky...
+    /// Syncs every linked member of a single guild.
+    pub async fn sync_guild<T>(config: &'static Config, cache_http: T, guild_id: GuildId)
+    where
+        T: CacheHttp,
+    {
 
-        let Ok(current_user) = http.get_current_user().await else {
-            error!("Error getting current user");
-            return false;
+        let http: &Http = cache_http.http();
+
+        config.discord_budget.acquire().await;
+
+        let Ok(mut guild) = http.get_guild(guild_id).await else {
+            error!("Error attempting to get guild.");
+            return;
         };
 
-        let Ok(guild_user) = guild.member(http, current_user.id).await else {
-            error!("Error getting current user guild member object");
-            return false;
+        let Ok(users) = config.database().fetch_users().await else {
+            error!("Could not get users from database");
+            return;
         };
 
-        let Some(default_channel) = guild.default_channel(current_user.id) else {
-            error!("No default channel found in guild {}.", guild.name);
-            return false;
+        let Ok(guild_links) = config.database().fetch_guild_links().await else {
+            error!("Could not get guild links from database");
+            return;
         };
 
-        let permissions = guild.user_permissions_in(default_channel, &*guild_user);
+        let mut linked: HashMap<String, String> = users.into_iter()
+            .filter(|user| user.is_primary)
+            .map(|user| (user.discord_id, user.faceit_id))
+            .collect();
 
-        if !permissions.manage_nicknames() && !permissions.manage_roles() {
-            error!("Cannot manage nicknames and/or roles in guild {}.", guild.name);
-            return false;
+        for link in guild_links.into_iter().filter(|link| link.guild_id == guild_id.to_string()) {
+            match link.faceit_id {
+                Some(faceit_id) => linked.insert(link.discord_id, faceit_id),
+                None => linked.remove(&link.discord_id),
+            };
         }
 
-        */
+        let mut members = Vec::new();
+        let mut after = None;
+
+        loop {
+            config.discord_budget.acquire().await;
+
+            let Ok(page) = http.get_guild_members(guild_id, Some(1000), after).await else {
+                error!("Could not get members from guild {}.", guild.name);
+                return;
+            };
+
+            let done = page.len() < 1000;
+            after = page.last().map(|member| member.user.id.get());
+
+            members.extend(page.into_iter().filter(|member| linked.contains_key(&member.user.id.to_string())));
+
+            if done {
+                break;
+            }
+        }
+
+        info!("Syncing {} linked members in guild '{}'.", members.len(), guild.name);
+
+        let settings = GuildSettings::load(config, guild_id).await;
+
+        for (index, member) in members.iter().enumerate() {
+
+            let Some(faceit_id) = linked.get(&member.user.id.to_string()) else { continue };
+
+            match config.faceit().get_faceit_user_by_id(faceit_id).await {
+                Ok(Some(player)) => {
+                    let state = Self::player_state(config, member.user.id, &player, settings.wants_rank()).await;
+                    Self::apply_player(config, &cache_http, &mut guild, &settings, member.user.id, &state).await;
+                },
+                Ok(None) => info!("No player data for user '{}'", faceit_id),
+                Err(e) => error!("Error fetching Faceit user '{}': {}", faceit_id, e),
+            }
+
+            if (index + 1) % 25 == 0 {
+                info!("Synced {}/{} linked members in guild '{}'.", index + 1, members.len(), guild.name);
+            }
+
+        }
+
+        info!("Finished syncing guild '{}'.", guild.name);
+
+    }
+
+    /// Syncs a single member of a guild using the account they are linked to there, see `guild_account`.
+    pub async fn sync_member<T>(config: &'static Config, cache_http: T, guild_id: GuildId, user_id: UserId) -> Result<bool, Error>
+    where
+        T: CacheHttp,
+    {
+
+        let Some(faceit_id) = Self::guild_account(config, guild_id, user_id).await? else {
+            return Ok(false);
+        };
+
+        let Some(player) = config.faceit().get_faceit_user_by_id(&faceit_id).await? else {
+            info!("No player data for user '{}'", faceit_id);
+            return Ok(false);
+        };
+
+        Self::parse_user_in_guild(config, &cache_http, guild_id, user_id, player).await;
+
+        Ok(true)
+    }
+
+    /// Sets the nickname and swaps the member's `managed` roles for the `wanted` ones, both requests count against `budget`.
+    #[allow(clippy::too_many_arguments)]
+    async fn edit_member<T>(cache_http: T, budget: &RateBudget, guild: &PartialGuild, access: &GuildAccess, member_id: UserId, nickname: NicknameEdit<'_>, wanted: &[RoleId], managed: &HashSet<RoleId>) -> EditOutcome
+    where
+        T: CacheHttp,
+    {
+
+        let http: &Http = cache_http.http();
+
+        budget.acquire().await;
+
+        let Ok(target_member) = guild.member(&http, &member_id).await else {
+            return EditOutcome::NotInGuild;
+        };
 
         if guild.owner_id == member_id {
-            info!("Cannot rename owner in guild {}.", guild.name);
-            return true;
+            return EditOutcome::IsOwner;
         }
 
-        /*
+        if !access.manage_nicknames && !access.manage_roles {
+            return EditOutcome::MissingPermission;
+        }
 
-        let all_roles: &Vec<RoleId> = &prepared_guild.values().cloned().collect();
+        if !access.can_edit_member(guild, &target_member) {
+            return EditOutcome::HierarchyTooLow;
+        }
 
-        let mut target_roles: Vec<RoleId> = target_member.roles.clone()
-            .into_iter()
-            .filter(|role| !all_roles.contains(role))
-            .collect();
+        let mut builder = EditMember::new();
+        let mut changed = false;
+
+        let current_name = target_member.nick.as_deref().unwrap_or("");
+
+        let new_name = match nickname {
+            NicknameEdit::Set(name) => name,
+            NicknameEdit::ResetTemplated(templated) if templated.is_match(current_name) => "",
+            NicknameEdit::ResetTemplated(_) => current_name,
+        };
+
+        if access.manage_nicknames && current_name != new_name {
+            builder = builder.nickname(new_name);
+            changed = true;
+        }
+
+        if access.manage_roles {
 
-         */
+            let mut target_roles = target_member.roles.clone();
 
-        let mut target_roles = target_member.roles;
+            // Managed roles above the bot can't be removed, so they are left alone.
+            target_roles.retain(|role| !managed.contains(role) || !access.can_assign(guild, *role));
 
-        let guild_roles = &guild.roles;
+            for role in wanted.iter().filter(|role| access.can_assign(guild, **role)) {
+                if !target_roles.contains(role) {
+                    target_roles.push(*role);
+                }
+            }
 
-        for (key, role) in guild_roles.iter() {
-            if ALL_ROLES.contains(&&*role.name) && target_roles.contains(key) {
-                target_roles.retain(|&x| x.get() != key.get());
+            let mut current_roles = target_member.roles.clone();
+            current_roles.sort();
+            let mut sorted_roles = target_roles.clone();
+            sorted_roles.sort();
+
+            if current_roles != sorted_roles {
+                builder = builder.roles(target_roles);
+                changed = true;
             }
         }
 
-        if role.is_some() {
-            target_roles.push(role.unwrap());
+        if !changed {
+            return EditOutcome::Unchanged;
         }
 
-        let result = guild.edit_member(http, member_id, EditMember::new().nickname(new_name).roles(target_roles)).await;
+        budget.acquire().await;
+
+        let result = guild.edit_member(http, member_id, builder).await;
 
         match result {
             Ok(_) => {
                 //info!("Successfully edited guild member '{}' in guild '{}'.", member_id, guild.name);
-                true
+                EditOutcome::Edited
             },
             Err(e) => {
-                error!("Error when attempting to edit guild member '{}' in guild '{}'.", member_id, guild.name);
-                false
+                error!("Error when attempting to edit guild member '{}' in guild '{}': {}", member_id, guild.name, e);
+                EditOutcome::ApiError(e.to_string())
             }
         }
 
@@ -259,11 +1124,13 @@ impl DiscordBot {
 
 #[async_trait]
 impl EventHandler for DiscordBot {
-    async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: Option<bool>) {
+    async fn guild_create(&self, ctx: Context, guild: Guild, is_new: Option<bool>) {
+
+        let _work = self.supervisor.track();
 
         info!("Connection to guild '{}' established!", guild.name);
 
-        let success = prepare_guild(ctx, &guild).await;
+        let success = prepare_guild(self.config, ctx.clone(), &guild).await;
 
         if success {
             info!("Guild {} prepared successfully!", guild.name);
@@ -271,6 +1138,29 @@ impl EventHandler for DiscordBot {
             error!("Guild {} could not be prepared successfully!", guild.name);
         }
 
+        Self::check_guild(&ctx, guild.id).await;
+
+        // Existing guilds are kept up to date by the name syncer, only newly (re)joined ones need a full pass.
+        if success && is_new.unwrap_or(false) {
+            Self::sync_guild(self.config, &ctx, guild.id).await;
+        }
+
+    }
+
+    async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
+
+        if new_member.user.bot {
+            return;
+        }
+
+        let _work = self.supervisor.track();
+
+        match Self::sync_member(self.config, &ctx, new_member.guild_id, new_member.user.id).await {
+            Ok(true) => info!("Synced new member '{}' in guild '{}'.", new_member.user.name, new_member.guild_id),
+            Ok(false) => {},
+            Err(e) => error!("Error syncing new member '{}' in guild '{}': {}", new_member.user.name, new_member.guild_id, e),
+        }
+
     }
 
     async fn guild_delete(&self, _ctx: Context, incomplete: UnavailableGuild, full: Option<Guild>) {
@@ -297,7 +1187,7 @@ impl EventHandler for DiscordBot {
 
 }
 
-async fn prepare_guild(ctx: Context, guild: &Guild) -> bool {
+async fn prepare_guild(config: &'static Config, ctx: Context, guild: &Guild) -> bool {
 
     info!("Preparing guild '{}' with ID '{}'!", guild.name, guild.id);
 
@@ -318,7 +1208,7 @@ async fn prepare_guild(ctx: Context, guild: &Guild) -> bool {
     let mut actual_roles: HashMap<&str, RoleId> = HashMap::new();
 
     for (role, color) in &required_roles {
-        if let Some((key, _value)) = roles.iter().find(|(_, &ref v)| v.name.as_str() == *role) {
+        if let Some((key, _value)) = roles.iter().find(|(_, v)| v.name.as_str() == *role) {
             actual_roles.insert(*role, *key);
         } else {
             info!("Role '{}' not found, attempting to create!",role);
@@ -342,8 +1232,41 @@ async fn prepare_guild(ctx: Context, guild: &Guild) -> bool {
         }
     }
 
+    let settings = GuildSettings::load(config, guild.id).await;
+
+    if settings.locations.region && !location::create_region_roles(&ctx.http, guild.id, roles).await {
+        return false;
+    }
+
+    if settings.challenger && !create_challenger_role(&ctx.http, guild.id, roles).await {
+        return false;
+    }
+
     info!("Guild prepared!");
 
     true
 
-}
\ No newline at end of file
+}
+
+/// Creates the Challenger role right above the level 10 role, unless the guild has it already.
+pub(crate) async fn create_challenger_role(http: &Http, guild_id: GuildId, roles: &HashMap<RoleId, Role>) -> bool {
+
+    if roles.values().any(|role| role.name == CHALLENGER_ROLE) {
+        return true;
+    }
+
+    info!("Role '{}' not found, attempting to create!", CHALLENGER_ROLE);
+
+    let mut builder = EditRole::new().name(CHALLENGER_ROLE).colour(Colour::new(CHALLENGER_COLOUR)).hoist(true).mentionable(true);
+
+    if let Some(level_10) = roles.values().find(|role| role.name == ALL_ROLES[9]) {
+        builder = builder.position(level_10.position + 1);
+    }
+
+    if let Err(e) = guild_id.create_role(http, builder).await {
+        error!("Failed to create role in guild '{}' reason: {}", guild_id, e);
+        return false;
+    }
+
+    true
+}
@@ -0,0 +1,252 @@
+use regex::Regex;
+use crate::config::Config;
+use crate::database::GuildConfig;
+
+pub const DEFAULT_TEMPLATE: &str = "({elo} ELO) {nickname}";
+
+const PLACEHOLDERS: &[&str] = &["{elo}", "{level}", "{rank}", "{nickname}"];
+
+// Placeholders which render as digits, a Faceit nickname next to them could not be told apart when restoring.
+const NUMERIC_PLACEHOLDERS: &[&str] = &["{elo}", "{level}", "{rank}"];
+
+// Longest nickname Discord allows, in characters.
+const MAX_NICKNAME_LENGTH: usize = 32;
+
+// Faceit nicknames are at most 12 characters long.
+const MAX_FACEIT_NICKNAME_LENGTH: usize = 12;
+
+/// The values a nickname template can use.
+pub(crate) struct Nickname {
+    pub elo: String,
+    pub level: usize,
+    /// Position in the regional ranking, only looked up for level 10 players.
+    pub rank: Option<u64>,
+    pub faceit_nickname: String,
+}
+
+impl Nickname {
+
+    /// The longest values a player can have, for checking templates against Discord's nickname limit.
+    fn longest() -> Self {
+        Nickname {
+            elo: String::from("99999"),
+            level: 10,
+            rank: Some(999_999),
+            faceit_nickname: "W".repeat(MAX_FACEIT_NICKNAME_LENGTH),
+        }
+    }
+
+}
+
+/// How a guild wants nicknames of linked members to look, e.g. '({elo} ELO) {nickname}'.
+pub(crate) struct NicknameTemplate {
+    template: String,
+}
+
+impl NicknameTemplate {
+
+    /// The configured default, which was validated on startup.
+    pub fn configured(config: &Config) -> Self {
+        NicknameTemplate { template: config.nickname_template.clone() }
+    }
+
+    /// Checks that the template only uses known placeholders and contains the Faceit nickname exactly once.
+    pub fn parse(template: &str) -> Result<Self, String> {
+
+        if template.matches("{nickname}").count() != 1 {
+            return Err(String::from("The format has to contain '{nickname}' exactly once."));
+        }
+
+        let mut rest = template.to_string();
+        for placeholder in PLACEHOLDERS {
+            rest = rest.replace(placeholder, "");
+        }
+
+        if rest.contains(['{', '}']) {
+            return Err(format!("Unknown placeholder, only {} can be used.", PLACEHOLDERS.join(", ")));
+        }
+
+        // Players without a ranking position get no '{rank}', anything attached to it would be left dangling.
+        let words: Vec<&str> = template.split(' ').filter(|word| !word.is_empty()).collect();
+
+        for (index, _) in words.iter().enumerate().filter(|(_, word)| word.contains("{rank}")) {
+
+            if words[index] != "{rank}" {
+                return Err(String::from("'{rank}' has to be separated from the rest of the format by spaces, it is left out for players without a ranking position."));
+            }
+
+            // A word of only separators, like '|', belongs to the rank and would be all that's left of it.
+            let neighbours = [index.checked_sub(1).map(|before| words[before]), words.get(index + 1).copied()];
+
+            if neighbours.into_iter().flatten().any(|word| !word.contains('{') && !word.chars().any(char::is_alphanumeric)) {
+                return Err(String::from("'{rank}' can't be next to a separator like '|', it is left out for players without a ranking position."));
+            }
+        }
+
+        if let Some(placeholder) = NUMERIC_PLACEHOLDERS.iter().find(|placeholder| {
+            template.contains(&format!("{{nickname}}{}", placeholder)) || template.contains(&format!("{}{{nickname}}", placeholder))
+        }) {
+            return Err(format!("'{{nickname}}' can't be right next to '{}', put a space or another character between them.", placeholder));
+        }
+
+        let parsed = NicknameTemplate { template: template.to_string() };
+
+        let longest = parsed.render(&Nickname::longest()).chars().count();
+
+        if longest > MAX_NICKNAME_LENGTH {
+            return Err(format!("Nicknames made from this format can be up to {} characters long, Discord only allows {}.", longest, MAX_NICKNAME_LENGTH));
+        }
+
+        Ok(parsed)
+    }
+
+    /// The guild's configured template, or the default if it has none or it can't be read.
+    pub fn for_guild(config: &Config, guild: &GuildConfig) -> Self {
+        guild.nickname_template.as_deref()
+            .and_then(|template| Self::parse(template).ok())
+            .unwrap_or_else(|| Self::configured(config))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    pub fn uses_rank(&self) -> bool {
+        self.template.contains("{rank}")
+    }
+
+    /// '{rank}' is left empty for players without a ranking position.
+    pub fn render(&self, nickname: &Nickname) -> String {
+
+        let rendered = self.template
+            .replace("{elo}", &nickname.elo)
+            .replace("{level}", &nickname.level.to_string())
+            .replace("{nickname}", &nickname.faceit_nickname);
+
+        match nickname.rank {
+            Some(rank) => rendered.replace("{rank}", &rank.to_string()),
+            // Discord trims nicknames, so the spaces around an empty rank are dropped the same way.
+            None => rendered.replace(" {rank}", "").replace("{rank} ", "").replace("{rank}", "").trim().to_string(),
+        }
+    }
+
+    /// Matches nicknames made from this template, capturing the Faceit nickname.
+    pub fn regex(&self) -> Regex {
+
+        let mut pattern = regex::escape(&self.template);
+
+        // Braces are escaped by `regex::escape`.
+        pattern = pattern
+            .replace(r"\{elo\}", r"\d+")
+            .replace(r"\{level\}", r"\d+")
+            .replace(r"\{rank\} ", r"(?:\d+ )?")
+            .replace(r" \{rank\}", r"(?: \d+)?")
+            .replace(r"\{rank\}", r"\d*")
+            .replace(r"\{nickname\}", r"([A-Za-z0-9_-]+)");
+
+        Regex::new(&format!("^{}$", pattern)).unwrap()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nickname(faceit_nickname: &str, rank: Option<u64>) -> Nickname {
+        Nickname { elo: String::from("2143"), level: 10, rank, faceit_nickname: faceit_nickname.to_string() }
+    }
+
+    /// Renders the nickname and reads the Faceit nickname back the way restore does.
+    fn round_trip(template: &str, faceit_nickname: &str, rank: Option<u64>) -> (String, Option<String>) {
+
+        let template = NicknameTemplate::parse(template).unwrap();
+        let rendered = template.render(&nickname(faceit_nickname, rank));
+
+        let recovered = template.regex().captures(&rendered).map(|captures| captures[1].to_string());
+
+        (rendered, recovered)
+    }
+
+    #[test]
+    fn default_template_round_trips() {
+
+        for faceit_nickname in ["player", "the-player", "the_player", "-_-", "12345"] {
+            let (rendered, recovered) = round_trip(DEFAULT_TEMPLATE, faceit_nickname, None);
+            assert_eq!(rendered, format!("(2143 ELO) {}", faceit_nickname));
+            assert_eq!(recovered.as_deref(), Some(faceit_nickname));
+        }
+    }
+
+    #[test]
+    fn rank_at_start_round_trips() {
+
+        assert_eq!(round_trip("{rank} {nickname}", "a-b_c", Some(7)), (String::from("7 a-b_c"), Some(String::from("a-b_c"))));
+        assert_eq!(round_trip("{rank} {nickname}", "a-b_c", None), (String::from("a-b_c"), Some(String::from("a-b_c"))));
+    }
+
+    #[test]
+    fn rank_in_middle_round_trips() {
+
+        assert_eq!(round_trip("{elo} {rank} {nickname}", "player_1", Some(123)), (String::from("2143 123 player_1"), Some(String::from("player_1"))));
+        assert_eq!(round_trip("{elo} {rank} {nickname}", "player_1", None), (String::from("2143 player_1"), Some(String::from("player_1"))));
+    }
+
+    #[test]
+    fn rank_at_end_round_trips() {
+
+        assert_eq!(round_trip("{nickname} {rank}", "-player-", Some(999)), (String::from("-player- 999"), Some(String::from("-player-"))));
+        assert_eq!(round_trip("{nickname} {rank}", "-player-", None), (String::from("-player-"), Some(String::from("-player-"))));
+    }
+
+    #[test]
+    fn rank_next_to_separator_is_rejected() {
+
+        // Unranked players would be left with '-player- |'.
+        assert!(NicknameTemplate::parse("{nickname} | {rank}").is_err());
+        assert!(NicknameTemplate::parse("{rank} - {nickname}").is_err());
+        assert!(NicknameTemplate::parse("[{rank}] {nickname}").is_err());
+        assert!(NicknameTemplate::parse("({elo} ELO) {rank} {nickname}").is_ok());
+    }
+
+    #[test]
+    fn level_round_trips() {
+
+        assert_eq!(round_trip("[{level}] {nickname}", "_x_", None), (String::from("[10] _x_"), Some(String::from("_x_"))));
+    }
+
+    #[test]
+    fn other_nicknames_do_not_match() {
+
+        let template = NicknameTemplate::parse(DEFAULT_TEMPLATE).unwrap();
+
+        assert!(!template.regex().is_match("player"));
+        assert!(!template.regex().is_match("(2143 ELO) two words"));
+    }
+
+    #[test]
+    fn rejects_ambiguous_templates() {
+
+        for template in [
+            "{elo}",
+            "{nickname} {nickname}",
+            "{nickname} {unknown}",
+            "{nickname} {elo",
+            "#{rank} {nickname}",
+            "{nickname} {rank}!",
+            "{elo}{nickname}",
+            "{nickname}{level}",
+            "{nickname} {rank}{elo}",
+        ] {
+            assert!(NicknameTemplate::parse(template).is_err(), "'{}' should be rejected", template);
+        }
+    }
+
+    #[test]
+    fn rejects_templates_too_long_for_discord() {
+
+        assert!(NicknameTemplate::parse("{nickname} {elo} {elo} {elo} {elo}").is_err());
+        assert!(NicknameTemplate::parse("{nickname} {elo} {elo} {elo}").is_ok());
+    }
+
+}
@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use regex::Regex;
+use serenity::all::{GuildId, Http, Member, UserId};
+use tracing::error;
+use crate::backup::csv_field;
+use crate::config::Config;
+use super::DiscordBot;
+
+/// A link restore would make for one member, with the reason it can't be made if there is one.
+pub(crate) struct Proposal {
+    pub guild_id: GuildId,
+    pub discord_id: UserId,
+    pub member: String,
+    pub parsed_nickname: String,
+    pub faceit_id: Option<String>,
+    pub faceit_nickname: Option<String>,
+    pub conflict: Option<String>,
+}
+
+/// Links guessed from member nicknames, built before anything is written so it can be previewed.
+#[derive(Default)]
+pub(crate) struct RestorePlan {
+    pub total: usize,
+    pub proposals: Vec<Proposal>,
+    /// Moderators restore links for their own guild only, see `DiscordBot::link_member`.
+    pub guild_scoped: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct RestoreSummary {
+    pub total: usize,
+    pub assumed: usize,
+    pub added: usize,
+    pub errors: usize,
+}
+
+impl fmt::Display for RestoreSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Total: {}, Assumed: {}, Added: {}, Errors: {}", self.total, self.assumed, self.added, self.errors)
+    }
+}
+
+impl RestorePlan {
+
+    /// Members of the guild along with its name, to be passed to `scan_member` one by one.
+    pub async fn guild_members(http: &Http, guild_id: GuildId) -> Option<(String, Vec<Member>)> {
+
+        let Ok(guild) = http.get_guild(guild_id).await else {
+            error!("Error attempting to get guild.");
+            return None;
+        };
+
+        let mut members = Vec::new();
+        let mut after = None;
+
+        // Discord hands out at most 1000 members at a time.
+        loop {
+            let Ok(page) = http.get_guild_members(guild_id, Some(1000), after).await else {
+                error!("Could not get members from guild {}.", guild.name);
+                return None;
+            };
+
+            let done = page.len() < 1000;
+            after = page.last().map(|member| member.user.id.get());
+
+            members.extend(page);
+
+            if done {
+                break;
+            }
+        }
+
+        Some((guild.name, members))
+    }
+
+    /// Adds a proposal for the member if their nickname was made from the guild's nickname template.
+    pub async fn scan_member(&mut self, config: &'static Config, guild_id: GuildId, member: &Member, parser: &Regex) {
+
+        self.total += 1;
+
+        // Members of several guilds are only proposed once.
+        if self.proposals.iter().any(|proposal| proposal.discord_id == member.user.id) {
+            return;
+        }
+
+        let Some(nickname) = &member.nick else {
+            return;
+        };
+
+        let Some(username) = parser.captures(nickname).and_then(|caps| caps.get(1)) else {
+            return;
+        };
+
+        let mut proposal = Proposal {
+            guild_id,
+            discord_id: member.user.id,
+            member: member.user.name.clone(),
+            parsed_nickname: username.as_str().to_string(),
+            faceit_id: None,
+            faceit_nickname: None,
+            conflict: None,
+        };
+
+        proposal.conflict = Self::resolve(config, &mut proposal).await;
+
+        self.proposals.push(proposal);
+    }
+
+    async fn resolve(config: &'static Config, proposal: &mut Proposal) -> Option<String> {
+
+        let player = match DiscordBot::resolve_player(config, &proposal.parsed_nickname).await {
+            Ok(Some(player)) => player,
+            Ok(None) => return Some(String::from("Faceit account not found.")),
+            Err(e) => {
+                error!("Error looking up Faceit account '{}': {}", proposal.parsed_nickname, e);
+                return Some(String::from("Faceit lookup failed."));
+            }
+        };
+
+        proposal.faceit_id = Some(player.player_id.clone());
+        proposal.faceit_nickname = Some(player.nickname.clone());
+
+        if player.get_player_skill_level().is_none() {
+            return Some(String::from("Has not played CS2 on Faceit."));
+        }
+
+        match config.database().is_blocked(proposal.discord_id.to_string(), player.player_id.clone()).await {
+            Ok(true) => return Some(String::from("Member or Faceit account is blocked from linking.")),
+            Ok(false) => {},
+            Err(e) => {
+                error!("Error checking blocklist for user '{}': {}", proposal.discord_id, e);
+                return Some(String::from("Database lookup failed."));
+            }
+        }
+
+        match config.database().user_exists(proposal.discord_id.to_string()).await {
+            Ok(true) => return Some(String::from("Member is already linked.")),
+            Ok(false) => {},
+            Err(e) => {
+                error!("Error checking link of user '{}': {}", proposal.discord_id, e);
+                return Some(String::from("Database lookup failed."));
+            }
+        }
+
+        match config.database().fetch_guild_link(proposal.guild_id.to_string(), proposal.discord_id.to_string()).await {
+            Ok(Some(_)) => return Some(String::from("Member was linked or unlinked by a moderator of this guild.")),
+            Ok(None) => {},
+            Err(e) => {
+                error!("Error checking guild link of user '{}': {}", proposal.discord_id, e);
+                return Some(String::from("Database lookup failed."));
+            }
+        }
+
+        match config.database().fetch_discord_ids(player.player_id.clone()).await {
+            Ok(discord_ids) if !discord_ids.is_empty() => Some(String::from("Faceit account is linked to another member.")),
+            Ok(_) => None,
+            Err(e) => {
+                error!("Error checking links of Faceit user '{}': {}", player.player_id, e);
+                Some(String::from("Database lookup failed."))
+            }
+        }
+    }
+
+    /// Flags Faceit accounts which several members claim, none of them can be linked safely.
+    pub fn finish(&mut self) {
+
+        let mut claims: HashMap<String, usize> = HashMap::new();
+
+        for faceit_id in self.proposals.iter().filter(|p| p.conflict.is_none()).filter_map(|p| p.faceit_id.clone()) {
+            *claims.entry(faceit_id).or_default() += 1;
+        }
+
+        let contested: HashSet<String> = claims.into_iter().filter(|(_, count)| *count > 1).map(|(id, _)| id).collect();
+
+        for proposal in self.proposals.iter_mut().filter(|p| p.conflict.is_none()) {
+            if proposal.faceit_id.as_ref().is_some_and(|id| contested.contains(id)) {
+                proposal.conflict = Some(String::from("Faceit account is claimed by several members."));
+            }
+        }
+    }
+
+    pub fn applicable(&self) -> usize {
+        self.proposals.iter().filter(|p| p.conflict.is_none()).count()
+    }
+
+    pub fn conflicts(&self) -> usize {
+        self.proposals.len() - self.applicable()
+    }
+
+    /// One row per proposal, for reviewing the plan before applying it.
+    pub fn to_csv(&self) -> String {
+
+        let mut csv = String::from("guild_id,discord_id,member,parsed_nickname,faceit_id,faceit_nickname,action,conflict\n");
+
+        for proposal in self.proposals.iter() {
+            let fields = [
+                proposal.guild_id.to_string(),
+                proposal.discord_id.to_string(),
+                proposal.member.clone(),
+                proposal.parsed_nickname.clone(),
+                proposal.faceit_id.clone().unwrap_or_default(),
+                proposal.faceit_nickname.clone().unwrap_or_default(),
+                String::from(if proposal.conflict.is_none() { "link" } else { "skip" }),
+                proposal.conflict.clone().unwrap_or_default(),
+            ];
+
+            let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+
+        csv
+    }
+
+    /// Writes every proposal without conflicts, returning the proposals which were linked.
+    pub async fn apply(&self, config: &'static Config, actor: UserId) -> (RestoreSummary, Vec<&Proposal>) {
+
+        let mut summary = RestoreSummary {
+            total: self.total,
+            assumed: self.proposals.len(),
+            errors: self.conflicts(),
+            ..Default::default()
+        };
+
+        let mut linked = Vec::new();
+
+        for proposal in self.proposals.iter().filter(|p| p.conflict.is_none()) {
+
+            let (Some(faceit_id), Some(faceit_nickname)) = (&proposal.faceit_id, &proposal.faceit_nickname) else {
+                continue;
+            };
+
+            // The member may have linked themselves since the plan was made.
+            if let Ok(true) = config.database().user_exists(proposal.discord_id.to_string()).await {
+                summary.errors += 1;
+                continue;
+            }
+
+            // Or been blocked while the plan waited for confirmation.
+            if !matches!(config.database().is_blocked(proposal.discord_id.to_string(), faceit_id.clone()).await, Ok(false)) {
+                summary.errors += 1;
+                continue;
+            }
+
+            let result = if self.guild_scoped {
+                config.database().set_guild_link(proposal.guild_id.to_string(), proposal.discord_id.to_string(), Some(faceit_id.clone()), actor.to_string()).await
+            } else {
+                config.database().add_user(faceit_id.clone(), proposal.discord_id.to_string(), Some(faceit_nickname.clone()), true).await
+            };
+
+            match result {
+                Ok(true) => {
+                    summary.added += 1;
+                    linked.push(proposal);
+                },
+                _ => {
+                    summary.errors += 1;
+                    error!("Error when linking user '{}' to faceit account '{}'", proposal.member, faceit_nickname);
+                }
+            }
+        }
+
+        (summary, linked)
+    }
+
+}
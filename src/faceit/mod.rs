@@ -1,88 +1,168 @@
-use std::env;
 use anyhow::Error;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
-
-pub struct Faceit;
+use serenity::model::Timestamp;
+use crate::metrics;
+use crate::ratelimit::RateBudget;
+
+/// Client for the Faceit data API, cheap to copy.
+#[derive(Clone, Copy, Debug)]
+pub struct Faceit {
+    token: &'static str,
+    budget: &'static RateBudget,
+}
 
 #[derive(Deserialize, Debug)]
 pub struct Player {
     pub player_id: String,
     pub nickname: String,
+    /// ISO 3166 country code in lowercase, such as 'se'.
+    #[serde(default)]
+    pub country: Option<String>,
     games: serde_json::Map<String, serde_json::Value>,
 }
 
 impl Player {
 
     pub fn get_player_elo(&self) -> Option<String> {
-        let Some(cs2_data) = self.games.get("cs2") else {
-            return None;
-        };
-        let Some(cs2_elo) = cs2_data.get("faceit_elo") else {
-            return None;
-        };
+        let cs2_data = self.games.get("cs2")?;
+        let cs2_elo = cs2_data.get("faceit_elo")?;
         Some(cs2_elo.to_string())
     }
 
     pub fn get_player_skill_level(&self) -> Option<usize> {
-        let Some(cs2_data) = self.games.get("cs2") else {
-            return None;
-        };
-        let Some(cs2_skill_level) = cs2_data.get("skill_level") else {
-            return None;
-        };
+        let cs2_data = self.games.get("cs2")?;
+        let cs2_skill_level = cs2_data.get("skill_level")?;
+
+        cs2_skill_level.to_string().parse::<usize>().ok()
+    }
+
+    /// Faceit matchmaking region of the player, such as 'EU' or 'NA'.
+    pub fn get_player_region(&self) -> Option<String> {
+        let cs2_data = self.games.get("cs2")?;
+        let cs2_region = cs2_data.get("region")?;
+
+        cs2_region.as_str().map(String::from)
+    }
 
-        match cs2_skill_level.to_string().parse::<usize>() {
-            Ok(number) => Some(number),
-            Err(e) => None,
+}
+
+/// A ban from the Faceit player bans endpoint.
+#[derive(Deserialize, Debug)]
+pub struct PlayerBan {
+    #[serde(default)]
+    pub reason: String,
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    /// Missing or null for permanent bans.
+    #[serde(default)]
+    pub ends_at: Option<String>,
+}
+
+impl PlayerBan {
+
+    /// Unix time the ban ends, `None` if it is permanent or the time can't be read.
+    pub fn ends_at_unix(&self) -> Option<i64> {
+        self.ends_at.as_deref().and_then(|ends_at| Timestamp::parse(ends_at).ok()).map(|ends_at| ends_at.unix_timestamp())
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        match &self.ends_at {
+            None => true,
+            Some(_) => self.ends_at_unix().is_none_or(|ends_at| ends_at > now),
         }
     }
 
 }
 
+#[derive(Deserialize, Debug)]
+struct PlayerRanking {
+    #[serde(default)]
+    position: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlayerBans {
+    #[serde(default)]
+    items: Vec<PlayerBan>,
+}
+
 impl Faceit {
 
-    pub async fn get_faceit_user_by_id(faceit_id: &String) -> Result<Option<Player>, Error> {
+    pub fn new(token: &'static str, budget: &'static RateBudget) -> Self {
+        Faceit { token, budget }
+    }
+
+    pub async fn get_faceit_user_by_id(&self, faceit_id: &String) -> Result<Option<Player>, Error> {
 
         let url = format!("https://open.faceit.com/data/v4/players/{}", faceit_id);
 
-        let results = Self::faceit_api_query(url).await?;
+        let results = self.faceit_api_query(url).await?;
 
         Ok(results)
 
     }
 
-    pub async fn get_faceit_user_by_nickname(username: String) -> Result<Option<Player>, Error> {
+    pub async fn get_faceit_user_by_nickname(&self, username: String) -> Result<Option<Player>, Error> {
 
         let url = format!("https://open.faceit.com/data/v4/players?nickname={}&game=cs2", username);
 
-        let results = Self::faceit_api_query(url).await?;
+        let results = self.faceit_api_query(url).await?;
 
         Ok(results)
 
     }
 
-    async fn faceit_api_query(url: String) -> Result<Option<Player>, Error>{
+    /// Current and past bans of a player, newest first.
+    pub async fn get_player_bans(&self, faceit_id: &str) -> Result<Vec<PlayerBan>, Error> {
+
+        let url = format!("https://open.faceit.com/data/v4/players/{}/bans", faceit_id);
+
+        let results: Option<PlayerBans> = self.faceit_api_query(url).await?;
+
+        Ok(results.map(|bans| bans.items).unwrap_or_default())
+
+    }
+
+    /// Position of the player in the CS2 ranking of their region, `None` if they are not ranked.
+    pub async fn get_player_ranking(&self, region: &str, faceit_id: &str) -> Result<Option<u64>, Error> {
+
+        // The endpoint also lists the players around the position, which isn't needed.
+        let url = format!("https://open.faceit.com/data/v4/rankings/games/cs2/regions/{}/players/{}?limit=1", region, faceit_id);
+
+        let results: Option<PlayerRanking> = self.faceit_api_query(url).await?;
+
+        Ok(results.and_then(|ranking| ranking.position))
+
+    }
+
+    async fn faceit_api_query<T: DeserializeOwned>(&self, url: String) -> Result<Option<T>, Error>{
 
-        let token = env::var("FACEIT_TOKEN").expect("Failed to get FACEIT_TOKEN!");
+        self.budget.acquire().await;
 
         let mut headers = HeaderMap::new();
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token))?);
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", self.token))?);
 
         let client = Client::new();
 
-        let response = client.get(url)
-            .headers(headers)
-            .send()
-            .await?;
+        let response = match client.get(url).headers(headers).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                metrics::FACEIT_REQUESTS.inc("error");
+                return Err(e.into());
+            }
+        };
+
+        metrics::FACEIT_REQUESTS.inc(response.status().as_str());
 
         if response.status().is_success() {
             let body = response.text().await?;
 
-            let player: Player = serde_json::from_str(&body)?;
+            let value: T = serde_json::from_str(&body)?;
 
-            Ok(Some(player))
+            Ok(Some(value))
         } else if response.status().is_client_error() {
             Ok(None)
         } else {
@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use serde::Serialize;
+use serenity::all::ShardManager;
+use serenity::gateway::ConnectionStage;
+use tracing::{error, info};
+use crate::database::Database;
+use crate::metrics;
+use crate::tasks::{Shutdown, Supervisor, TaskState};
+
+#[derive(Clone)]
+struct HealthState {
+    database: Database,
+    shard_manager: Arc<ShardManager>,
+    supervisor: Supervisor,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    gateway_connected: bool,
+    database_reachable: bool,
+    syncer_running: bool,
+    /// Unix time of the last successful sync.
+    last_sync_at: Option<u64>,
+}
+
+impl HealthReport {
+
+    fn healthy(&self) -> bool {
+        self.gateway_connected && self.database_reachable && self.syncer_running
+    }
+
+}
+
+/// Serves '/healthz' for orchestrators and '/metrics' for Prometheus.
+pub async fn serve(port: u16, database: Database, shard_manager: Arc<ShardManager>, supervisor: Supervisor, shutdown: Shutdown) {
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(prometheus))
+        .with_state(HealthState { database, shard_manager, supervisor });
+
+    info!("Serving health checks and metrics on {}", addr);
+
+    let server = axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.wait());
+
+    if let Err(e) = server.await {
+        error!("Health server stopped: {}", e);
+    }
+}
+
+async fn healthz(State(state): State<HealthState>) -> impl IntoResponse {
+
+    let gateway_connected = state.shard_manager.runners.lock().await
+        .values()
+        .any(|runner| runner.stage == ConnectionStage::Connected);
+
+    let database_reachable = match state.database.ping().await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Health check could not reach the database: {}", e);
+            false
+        }
+    };
+
+    let syncer_running = state.supervisor.health().get("sync scheduler")
+        .is_some_and(|health| health.state == TaskState::Running);
+
+    let report = HealthReport { gateway_connected, database_reachable, syncer_running, last_sync_at: metrics::SYNC.last_success_at() };
+
+    let status = if report.healthy() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (status, [(header::CONTENT_TYPE, "application/json")], serde_json::to_string(&report).unwrap_or_default())
+}
+
+async fn prometheus() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::render())
+}
@@ -0,0 +1,26 @@
+pub mod backup;
+pub mod bot;
+pub mod config;
+pub mod database;
+pub mod discord;
+pub mod faceit;
+pub mod health;
+pub mod metrics;
+pub mod ratelimit;
+pub mod syncer;
+pub mod tasks;
+pub mod webhook;
+
+use config::Config;
+use syncer::SyncScheduler;
+use tasks::Supervisor;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type PoiseContext<'a> = poise::Context<'a, Data, Error>;
+pub struct Data {
+    pub config: &'static Config,
+    pub scheduler: SyncScheduler,
+    pub supervisor: Supervisor,
+    /// When each user last used '/refresh' on themselves.
+    pub refresh_cooldowns: std::sync::Mutex<std::collections::HashMap<serenity::all::UserId, std::time::Instant>>,
+}
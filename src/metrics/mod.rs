@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use crate::database::unix_now;
+
+/// Faceit API requests by HTTP status, or 'error' when no response came back.
+pub static FACEIT_REQUESTS: LazyLock<LabeledCounter> = LazyLock::new(Default::default);
+
+/// Member edits by outcome, see `EditOutcome::as_str`.
+pub static DISCORD_EDITS: LazyLock<LabeledCounter> = LazyLock::new(Default::default);
+
+/// Lookups of the bot's own member by whether the cache had it.
+pub static CACHE_LOOKUPS: LazyLock<LabeledCounter> = LazyLock::new(Default::default);
+
+pub static SYNC: LazyLock<SyncMetrics> = LazyLock::new(Default::default);
+
+/// Counter split by a single label value.
+#[derive(Default)]
+pub struct LabeledCounter {
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl LabeledCounter {
+
+    pub fn inc(&self, label: &str) {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        *values.entry(label.to_string()).or_default() += 1;
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.values.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+}
+
+/// A cycle is the time between two reloads of the sync scheduler.
+pub struct SyncMetrics {
+    syncs: AtomicU64,
+    sync_millis: AtomicU64,
+    last_success_at: AtomicU64,
+    cycle_users: AtomicU64,
+    cycle: Mutex<CycleState>,
+}
+
+struct CycleState {
+    started_at: Instant,
+    last_duration: Option<Duration>,
+    last_users: u64,
+}
+
+impl Default for SyncMetrics {
+    fn default() -> Self {
+        SyncMetrics {
+            syncs: AtomicU64::new(0),
+            sync_millis: AtomicU64::new(0),
+            last_success_at: AtomicU64::new(0),
+            cycle_users: AtomicU64::new(0),
+            cycle: Mutex::new(CycleState { started_at: Instant::now(), last_duration: None, last_users: 0 }),
+        }
+    }
+}
+
+impl SyncMetrics {
+
+    /// Records a user whose nickname and roles were brought up to date.
+    pub fn synced(&self, took: Duration) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        self.sync_millis.fetch_add(took.as_millis() as u64, Ordering::Relaxed);
+        self.cycle_users.fetch_add(1, Ordering::Relaxed);
+        self.last_success_at.store(unix_now() as u64, Ordering::Relaxed);
+    }
+
+    pub fn finish_cycle(&self) {
+        let mut cycle = self.cycle.lock().unwrap_or_else(|e| e.into_inner());
+        cycle.last_duration = Some(cycle.started_at.elapsed());
+        cycle.last_users = self.cycle_users.swap(0, Ordering::Relaxed);
+        cycle.started_at = Instant::now();
+    }
+
+    /// Unix time of the last successful sync, if there was one.
+    pub fn last_success_at(&self) -> Option<u64> {
+        Some(self.last_success_at.load(Ordering::Relaxed)).filter(|at| *at != 0)
+    }
+
+}
+
+/// Every metric in the Prometheus text format.
+pub fn render() -> String {
+
+    let mut out = String::new();
+
+    labeled(&mut out, "plumpen_faceit_requests_total", "Faceit API requests by HTTP status.", "status", &FACEIT_REQUESTS);
+    labeled(&mut out, "plumpen_discord_edits_total", "Member edits by outcome.", "outcome", &DISCORD_EDITS);
+    labeled(&mut out, "plumpen_cache_lookups_total", "Lookups of the bot member by cache result.", "result", &CACHE_LOOKUPS);
+
+    let syncs = SYNC.syncs.load(Ordering::Relaxed);
+    let sync_seconds = SYNC.sync_millis.load(Ordering::Relaxed) as f64 / 1000.0;
+    single(&mut out, "plumpen_syncs_total", "Users synced.", "counter", syncs as f64);
+    single(&mut out, "plumpen_sync_seconds_total", "Time spent syncing users.", "counter", sync_seconds);
+
+    {
+        let cycle = SYNC.cycle.lock().unwrap_or_else(|e| e.into_inner());
+        let duration = cycle.last_duration.map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        single(&mut out, "plumpen_sync_cycle_duration_seconds", "Duration of the last sync cycle.", "gauge", duration);
+        single(&mut out, "plumpen_sync_cycle_users", "Users synced in the last sync cycle.", "gauge", cycle.last_users as f64);
+    }
+
+    let last_success = SYNC.last_success_at().unwrap_or(0);
+    single(&mut out, "plumpen_last_successful_sync_timestamp_seconds", "Unix time of the last successful sync.", "gauge", last_success as f64);
+
+    out
+}
+
+fn labeled(out: &mut String, name: &str, help: &str, label: &str, counter: &LabeledCounter) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (value, count) in counter.snapshot() {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value.replace('\\', "\\\\").replace('"', "\\\""), count);
+    }
+}
+
+fn single(out: &mut String, name: &str, help: &str, kind: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+}
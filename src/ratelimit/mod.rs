@@ -0,0 +1,134 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+/// Token bucket which allows short bursts but keeps the average below a per minute budget.
+#[derive(Debug)]
+pub struct RateBudget {
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    per_minute: u32,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl BudgetState {
+
+    fn refill(&mut self) {
+        let rate = self.per_minute as f64 / 60.0;
+        let elapsed = self.refilled_at.elapsed().as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(self.per_minute as f64);
+        self.refilled_at = Instant::now();
+    }
+
+}
+
+impl RateBudget {
+
+    pub fn new(per_minute: u32) -> Self {
+        RateBudget {
+            state: Mutex::new(BudgetState {
+                per_minute: per_minute.max(1),
+                tokens: per_minute.max(1) as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until the budget allows another request.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                state.refill();
+
+                if state.tokens >= 1.0 {
+                    state.tokens -= 1.0;
+                    return;
+                }
+
+                let rate = state.per_minute as f64 / 60.0;
+                Duration::from_secs_f64((1.0 - state.tokens) / rate)
+            };
+
+            sleep(wait).await;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_up_to_the_budget() {
+
+        let budget = RateBudget::new(60);
+        let started_at = Instant::now();
+
+        for _ in 0..60 {
+            budget.acquire().await;
+        }
+
+        assert_eq!(started_at.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_a_refill_once_spent() {
+
+        let budget = RateBudget::new(60);
+
+        for _ in 0..60 {
+            budget.acquire().await;
+        }
+
+        let started_at = Instant::now();
+
+        budget.acquire().await;
+        budget.acquire().await;
+
+        // One token a second at 60 per minute.
+        let waited = started_at.elapsed();
+        assert!(waited >= Duration::from_secs(2) && waited < Duration::from_millis(2100), "waited {:?}", waited);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refill_stops_at_the_budget() {
+
+        let budget = RateBudget::new(30);
+
+        budget.acquire().await;
+
+        tokio::time::advance(Duration::from_secs(10 * 60)).await;
+
+        let started_at = Instant::now();
+
+        for _ in 0..30 {
+            budget.acquire().await;
+        }
+
+        assert_eq!(started_at.elapsed(), Duration::ZERO);
+
+        budget.acquire().await;
+
+        assert!(started_at.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_budget_still_allows_one_per_minute() {
+
+        let budget = RateBudget::new(0);
+        let started_at = Instant::now();
+
+        budget.acquire().await;
+        assert_eq!(started_at.elapsed(), Duration::ZERO);
+
+        budget.acquire().await;
+        assert!(started_at.elapsed() >= Duration::from_secs(60));
+    }
+
+}
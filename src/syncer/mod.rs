@@ -0,0 +1,634 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serenity::all::{Cache, GuildId, Http, UserId};
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+use tracing::{error, info};
+use crate::config::Config;
+use crate::database::{BlockedEntry, GuildLink, LinkedUser};
+use crate::discord::DiscordBot;
+use crate::faceit::Player;
+use crate::metrics;
+use crate::tasks::Shutdown;
+
+// Faceit can take a few refreshes to show the ELO from a finished match, the announcement waits this long for it.
+const MATCH_ANNOUNCE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    /// How often players with a recent ELO change are refreshed.
+    pub active_interval: Duration,
+    /// How often everyone else is refreshed.
+    pub idle_interval: Duration,
+    /// How long a player counts as active after their ELO changed.
+    pub active_window: Duration,
+    /// How often the list of linked users is reloaded from the database.
+    pub reload_interval: Duration,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            active_interval: Duration::from_secs(2 * 60),
+            idle_interval: Duration::from_secs(30 * 60),
+            active_window: Duration::from_secs(3 * 60 * 60),
+            reload_interval: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+struct Entry {
+    /// The primary account, missing for members who are only linked by moderators.
+    faceit_id: Option<String>,
+    /// Guilds whose moderators linked the member to an account, synced on their own.
+    guild_links: Vec<GuildId>,
+    nickname: Option<String>,
+    last_elo: Option<String>,
+    /// When the ELO last changed, or the user was linked or enqueued.
+    active_at: Option<Instant>,
+    due: Instant,
+    /// Set when a finished match was reported, so an ELO change before then is announced.
+    announce_until: Option<Instant>,
+    /// Linked accounts besides the primary, only looked up to follow renames.
+    other_accounts: Vec<Account>,
+    /// When the other accounts were last looked up, they are due once per idle interval.
+    others_checked_at: Option<Instant>,
+}
+
+#[derive(Clone)]
+struct Account {
+    faceit_id: String,
+    nickname: Option<String>,
+}
+
+/// What the database knows about a member, used when reloading.
+#[derive(Default)]
+struct Linked {
+    faceit_id: Option<String>,
+    nickname: Option<String>,
+    other_accounts: Vec<Account>,
+    guild_links: Vec<GuildLink>,
+}
+
+/// Blocked Discord users and Faceit accounts, the scheduler doesn't sync them.
+#[derive(Default)]
+struct Blocklist {
+    discord_ids: HashSet<String>,
+    faceit_ids: HashSet<String>,
+}
+
+impl Blocklist {
+
+    fn new(entries: Vec<BlockedEntry>) -> Self {
+        let mut blocklist = Blocklist::default();
+        for entry in entries {
+            match entry.kind.as_str() {
+                "discord" => blocklist.discord_ids.insert(entry.id),
+                _ => blocklist.faceit_ids.insert(entry.id),
+            };
+        }
+        blocklist
+    }
+
+    fn blocks(&self, discord_id: &str, faceit_id: Option<&str>) -> bool {
+        self.discord_ids.contains(discord_id) || faceit_id.is_some_and(|faceit_id| self.faceit_ids.contains(faceit_id))
+    }
+
+}
+
+struct Inner {
+    config: &'static Config,
+    entries: Mutex<HashMap<UserId, Entry>>,
+    notify: Notify,
+    reload_requested: AtomicBool,
+}
+
+/// Refreshes linked users by priority, active players often and idle players rarely.
+#[derive(Clone)]
+pub struct SyncScheduler {
+    inner: Arc<Inner>,
+}
+
+impl SyncScheduler {
+
+    pub fn new(config: &'static Config) -> Self {
+        SyncScheduler {
+            inner: Arc::new(Inner {
+                config,
+                entries: Mutex::new(HashMap::new()),
+                notify: Notify::new(),
+                reload_requested: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Refreshes a user as soon as possible and treats them as active, or forgets them if they are no longer linked or blocked.
+    pub async fn enqueue(&self, discord_id: UserId) {
+        self.schedule(discord_id, false).await;
+    }
+
+    /// Same as `enqueue`, and announces the ELO change once the refresh picks it up.
+    pub async fn enqueue_match(&self, discord_id: UserId) {
+        self.schedule(discord_id, true).await;
+    }
+
+    /// Reloads the linked users from the database without waiting for the reload interval, e.g. after an import.
+    pub fn reload_now(&self) {
+        self.inner.reload_requested.store(true, Ordering::Relaxed);
+        self.inner.notify.notify_one();
+    }
+
+    async fn schedule(&self, discord_id: UserId, announce_match: bool) {
+
+        let blocklist = match self.inner.config.database().fetch_blocklist().await {
+            Ok(entries) => Blocklist::new(entries),
+            Err(e) => {
+                error!("Could not enqueue user '{}': {}", discord_id, e);
+                return;
+            }
+        };
+
+        let accounts = match self.inner.config.database().fetch_accounts(discord_id.to_string()).await {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                error!("Could not enqueue user '{}': {}", discord_id, e);
+                return;
+            }
+        };
+
+        let guild_links = match self.inner.config.database().fetch_member_guild_links(discord_id.to_string()).await {
+            Ok(links) => linked_guilds(links.into_iter().filter(|link| !blocklist.blocks(&link.discord_id, link.faceit_id.as_deref())).collect()),
+            Err(e) => {
+                error!("Could not enqueue user '{}': {}", discord_id, e);
+                return;
+            }
+        };
+
+        {
+            let mut entries = self.entries();
+
+            let (primaries, others): (Vec<LinkedUser>, Vec<LinkedUser>) = accounts.into_iter()
+                .filter(|account| !blocklist.blocks(&account.discord_id, Some(&account.faceit_id)))
+                .partition(|account| account.is_primary);
+            let primary = primaries.into_iter().next();
+
+            match (primary, guild_links) {
+                (None, guild_links) if guild_links.is_empty() => {
+                    entries.remove(&discord_id);
+                }
+                (primary, guild_links) => {
+                    let now = Instant::now();
+                    let faceit_id = primary.as_ref().map(|primary| primary.faceit_id.clone());
+                    let entry = entries.entry(discord_id).or_insert_with(|| Entry {
+                        faceit_id: faceit_id.clone(),
+                        guild_links: Vec::new(),
+                        nickname: None,
+                        last_elo: None,
+                        active_at: None,
+                        due: now,
+                        announce_until: None,
+                        other_accounts: Vec::new(),
+                        others_checked_at: None,
+                    });
+
+                    if entry.faceit_id != faceit_id {
+                        entry.last_elo = None;
+                    }
+
+                    entry.faceit_id = faceit_id;
+                    entry.guild_links = guild_links;
+                    entry.nickname = primary.and_then(|primary| primary.nickname);
+                    entry.other_accounts = others.into_iter().map(Account::from).collect();
+                    entry.active_at = Some(now);
+                    entry.due = now;
+                    if announce_match {
+                        entry.announce_until = Some(now + MATCH_ANNOUNCE_WINDOW);
+                    }
+                }
+            }
+        }
+
+        self.inner.notify.notify_one();
+    }
+
+    /// Syncs users as they become due until shutdown, a sync which already started is finished first.
+    pub async fn run(self, cache: Arc<Cache>, http: Arc<Http>, shutdown: Shutdown) {
+
+        info!("Starting sync scheduler");
+
+        let mut reload_at = Instant::now();
+
+        while !shutdown.is_triggered() {
+
+            let now = Instant::now();
+
+            if now >= reload_at || self.inner.reload_requested.swap(false, Ordering::Relaxed) {
+                metrics::SYNC.finish_cycle();
+                self.reload().await;
+                reload_at = now + self.inner.config.scheduler.reload_interval;
+            }
+
+            let next = self.entries().iter()
+                .min_by_key(|(_, entry)| entry.due)
+                .map(|(discord_id, entry)| (*discord_id, entry.due));
+
+            match next {
+                Some((discord_id, due)) if due <= now => {
+                    self.sync(&cache, &http, discord_id).await;
+                }
+                next => {
+                    let wake_at = next.map(|(_, due)| due.min(reload_at)).unwrap_or(reload_at);
+
+                    tokio::select! {
+                        _ = sleep_until(wake_at) => {},
+                        _ = self.inner.notify.notified() => {},
+                        _ = shutdown.clone().wait() => {},
+                    }
+                }
+            }
+
+        }
+
+        info!("Sync scheduler stopped");
+    }
+
+    /// Picks up new links and drops removed or blocked ones, keeping the schedule of everyone else.
+    async fn reload(&self) {
+
+        // Keep the current schedule if the database is unavailable rather than starting over.
+        let users = match self.inner.config.database().fetch_users().await {
+            Ok(users) => users,
+            Err(e) => {
+                error!("Could not get users from database: {}", e);
+                return;
+            }
+        };
+
+        let guild_links = match self.inner.config.database().fetch_guild_links().await {
+            Ok(links) => links,
+            Err(e) => {
+                error!("Could not get guild links from database: {}", e);
+                return;
+            }
+        };
+
+        let blocklist = match self.inner.config.database().fetch_blocklist().await {
+            Ok(entries) => Blocklist::new(entries),
+            Err(e) => {
+                error!("Could not get blocklist from database: {}", e);
+                return;
+            }
+        };
+
+        let now = Instant::now();
+        let mut entries = self.entries();
+        let mut linked: HashMap<UserId, Linked> = HashMap::new();
+
+        // Blocked users were cleared once when they were blocked, see `DiscordBot::clear_blocked`.
+        for user in users.into_iter().filter(|user| !blocklist.blocks(&user.discord_id, Some(&user.faceit_id))) {
+            let Ok(u64_id) = user.discord_id.parse::<u64>() else {
+                continue;
+            };
+            let linked = linked.entry(UserId::new(u64_id)).or_default();
+            if user.is_primary {
+                linked.faceit_id = Some(user.faceit_id);
+                linked.nickname = user.nickname;
+            } else {
+                linked.other_accounts.push(Account::from(user));
+            }
+        }
+
+        for link in guild_links.into_iter().filter(|link| !blocklist.blocks(&link.discord_id, link.faceit_id.as_deref())) {
+            let Ok(u64_id) = link.discord_id.parse::<u64>() else {
+                continue;
+            };
+            linked.entry(UserId::new(u64_id)).or_default().guild_links.push(link);
+        }
+
+        linked.retain(|_, user| user.faceit_id.is_some() || user.guild_links.iter().any(|link| link.faceit_id.is_some()));
+
+        entries.retain(|discord_id, _| linked.contains_key(discord_id));
+
+        for (discord_id, Linked { faceit_id, nickname, other_accounts, guild_links: links }) in linked {
+            let entry = entries.entry(discord_id).or_insert_with(|| Entry {
+                faceit_id: faceit_id.clone(),
+                guild_links: Vec::new(),
+                nickname: nickname.clone(),
+                last_elo: None,
+                active_at: None,
+                due: now,
+                announce_until: None,
+                other_accounts: Vec::new(),
+                others_checked_at: None,
+            });
+
+            if entry.faceit_id != faceit_id {
+                entry.faceit_id = faceit_id;
+                entry.last_elo = None;
+                entry.due = now;
+            }
+
+            entry.guild_links = linked_guilds(links);
+            entry.nickname = nickname;
+            entry.other_accounts = other_accounts;
+        }
+
+        info!("Sync scheduler tracking {} users.", entries.len());
+    }
+
+    async fn sync(&self, cache: &Arc<Cache>, http: &Arc<Http>, discord_id: UserId) {
+
+        let idle_interval = self.inner.config.scheduler.idle_interval;
+
+        let Some((faceit_id, guild_links, other_accounts)) = self.entries().get(&discord_id).map(|entry| {
+            let others_due = entry.others_checked_at.is_none_or(|at| at.elapsed() >= idle_interval);
+            let other_accounts = if others_due { entry.other_accounts.clone() } else { Vec::new() };
+            (entry.faceit_id.clone(), entry.guild_links.clone(), other_accounts)
+        }) else {
+            return;
+        };
+
+        let next_due = match faceit_id {
+            Some(faceit_id) => self.sync_primary(cache, http, discord_id, faceit_id).await,
+            None => Instant::now() + self.inner.config.scheduler.idle_interval,
+        };
+
+        for guild_id in guild_links {
+            if let Err(e) = DiscordBot::sync_member(self.inner.config, (cache, &**http), guild_id, discord_id).await {
+                error!("Could not sync user '{}' in guild '{}': {}", discord_id, guild_id, e);
+            }
+        }
+
+        let others_checked = !other_accounts.is_empty();
+
+        for account in other_accounts {
+            match self.inner.config.faceit().get_faceit_user_by_id(&account.faceit_id).await {
+                Ok(Some(player)) => self.track_nickname(http, discord_id, account.nickname.as_deref(), &player).await,
+                Ok(None) => info!("No player data for user '{}'", account.faceit_id),
+                Err(e) => error!("Could not fetch Faceit user '{}': {}", account.faceit_id, e),
+            }
+        }
+
+        if let Some(entry) = self.entries().get_mut(&discord_id) {
+            entry.due = next_due;
+            if others_checked {
+                entry.others_checked_at = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Applies the primary account in every guild without a moderator's link, returning when the user is due next.
+    async fn sync_primary(&self, cache: &Arc<Cache>, http: &Arc<Http>, discord_id: UserId, faceit_id: String) -> Instant {
+
+        let Some((nickname, last_elo, announce_until)) = self.entries().get(&discord_id).map(|entry| {
+            (entry.nickname.clone(), entry.last_elo.clone(), entry.announce_until)
+        }) else {
+            return Instant::now() + self.inner.config.scheduler.idle_interval;
+        };
+
+        let started_at = Instant::now();
+
+        let player = self.inner.config.faceit().get_faceit_user_by_id(&faceit_id).await;
+
+        match player {
+            Ok(Some(player)) => {
+                let elo = player.get_player_elo();
+
+                self.track_nickname(http, discord_id, nickname.as_deref(), &player).await;
+
+                info!("Syncing user '{}'.", player.nickname);
+
+                if let Some(announce_until) = announce_until {
+                    self.announce_match(http, discord_id, &player.nickname, announce_until, last_elo.as_deref(), elo.as_deref()).await;
+                }
+
+                DiscordBot::parse_user(self.inner.config, (cache, &**http), discord_id, player).await;
+
+                metrics::SYNC.synced(started_at.elapsed());
+
+                self.reschedule(discord_id, elo)
+            }
+            Ok(None) => {
+                info!("No player data for user '{}'", faceit_id);
+                Instant::now() + self.inner.config.scheduler.idle_interval
+            }
+            Err(e) => {
+                error!("Could not fetch Faceit user '{}': {}", faceit_id, e);
+                Instant::now() + self.inner.config.scheduler.active_interval
+            }
+        }
+    }
+
+    /// Announces the match once the ELO moved, the pending announcement is dropped after it or once the window passed.
+    async fn announce_match(&self, http: &Http, discord_id: UserId, nickname: &str, announce_until: Instant, old_elo: Option<&str>, new_elo: Option<&str>) {
+
+        let changed = match (old_elo, new_elo) {
+            (Some(old_elo), Some(new_elo)) if old_elo != new_elo => {
+                DiscordBot::announce_match(self.inner.config, http, discord_id, nickname, old_elo, new_elo).await;
+                true
+            }
+            _ => false,
+        };
+
+        if !changed && Instant::now() < announce_until {
+            return;
+        }
+
+        if let Some(entry) = self.entries().get_mut(&discord_id) {
+            entry.announce_until = None;
+        }
+    }
+
+    /// Marks the user as active if their ELO moved, and picks the next refresh time.
+    fn reschedule(&self, discord_id: UserId, elo: Option<String>) -> Instant {
+
+        let config = &self.inner.config.scheduler;
+        let now = Instant::now();
+        let mut entries = self.entries();
+
+        let Some(entry) = entries.get_mut(&discord_id) else {
+            return now + config.idle_interval;
+        };
+
+        if entry.last_elo.is_some() && entry.last_elo != elo {
+            entry.active_at = Some(now);
+        }
+
+        entry.last_elo = elo;
+
+        let active = entry.active_at.is_some_and(|at| now.duration_since(at) < config.active_window);
+
+        if active {
+            now + config.active_interval
+        } else {
+            now + config.idle_interval
+        }
+    }
+
+    async fn track_nickname(&self, http: &Http, discord_id: UserId, known: Option<&str>, player: &Player) {
+
+        if known == Some(player.nickname.as_str()) {
+            return;
+        }
+
+        // A fresh link is stored with the nickname holding the verification code, taking the code out is not announced.
+        let verifying = match self.inner.config.database().fetch_link_code(player.player_id.clone()).await {
+            Ok(code) => code.is_some(),
+            Err(e) => {
+                error!("Could not fetch link code for user '{}': {}", player.player_id, e);
+                return;
+            }
+        };
+
+        if let Err(e) = self.inner.config.database().update_nickname(player.player_id.clone(), player.nickname.clone()).await {
+            error!("Could not store nickname for user '{}': {}", player.player_id, e);
+            return;
+        }
+
+        if let Some(entry) = self.entries().get_mut(&discord_id) {
+            if entry.faceit_id.as_deref() == Some(player.player_id.as_str()) {
+                entry.nickname = Some(player.nickname.clone());
+            }
+            for account in entry.other_accounts.iter_mut().filter(|account| account.faceit_id == player.player_id) {
+                account.nickname = Some(player.nickname.clone());
+            }
+        }
+
+        if let Some(old_nickname) = known.filter(|_| !verifying) {
+            info!("Faceit user '{}' renamed from '{}' to '{}'.", player.player_id, old_nickname, player.nickname);
+            DiscordBot::announce_rename(self.inner.config, http, discord_id, old_nickname, &player.nickname).await;
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<UserId, Entry>> {
+        self.inner.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+}
+
+impl From<LinkedUser> for Account {
+    fn from(user: LinkedUser) -> Self {
+        Account { faceit_id: user.faceit_id, nickname: user.nickname }
+    }
+}
+
+/// Guilds in which a moderator linked the member to an account, unlinked guilds have nothing to sync.
+fn linked_guilds(links: Vec<GuildLink>) -> Vec<GuildId> {
+    links.into_iter()
+        .filter(|link| link.faceit_id.is_some())
+        .filter_map(|link| link.guild_id.parse::<u64>().ok())
+        .map(GuildId::new)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::advance;
+
+    const USER: UserId = UserId::new(1);
+
+    fn scheduler() -> SyncScheduler {
+
+        let config = Config::load(|key| match key {
+            "DISCORD_TOKEN" | "TURSO_DATABASE" | "TURSO_TOKEN" | "FACEIT_TOKEN" => Some(String::from("unused")),
+            "BOT_OWNER" => Some(String::from("1")),
+            "SYNC_ACTIVE_INTERVAL_SECS" => Some(String::from("60")),
+            "SYNC_IDLE_INTERVAL_SECS" => Some(String::from("600")),
+            "SYNC_ACTIVE_WINDOW_SECS" => Some(String::from("3600")),
+            _ => None,
+        }).unwrap();
+
+        let scheduler = SyncScheduler::new(Box::leak(Box::new(config)));
+
+        scheduler.entries().insert(USER, Entry {
+            faceit_id: Some(String::from("faceit")),
+            guild_links: Vec::new(),
+            nickname: None,
+            last_elo: None,
+            active_at: None,
+            due: Instant::now(),
+            announce_until: None,
+            other_accounts: Vec::new(),
+            others_checked_at: None,
+        });
+
+        scheduler
+    }
+
+    fn elo(elo: &str) -> Option<String> {
+        Some(elo.to_string())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn first_refresh_is_idle() {
+
+        let scheduler = scheduler();
+
+        assert_eq!(scheduler.reschedule(USER, elo("2000")) - Instant::now(), Duration::from_secs(600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn elo_change_makes_active() {
+
+        let scheduler = scheduler();
+
+        scheduler.reschedule(USER, elo("2000"));
+        assert_eq!(scheduler.reschedule(USER, elo("2000")) - Instant::now(), Duration::from_secs(600));
+        assert_eq!(scheduler.reschedule(USER, elo("2025")) - Instant::now(), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn active_until_window_passes() {
+
+        let scheduler = scheduler();
+
+        scheduler.reschedule(USER, elo("2000"));
+        scheduler.reschedule(USER, elo("2025"));
+
+        advance(Duration::from_secs(3599)).await;
+        assert_eq!(scheduler.reschedule(USER, elo("2025")) - Instant::now(), Duration::from_secs(60));
+
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(scheduler.reschedule(USER, elo("2025")) - Instant::now(), Duration::from_secs(600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn enqueued_users_start_active() {
+
+        let scheduler = scheduler();
+
+        scheduler.entries().get_mut(&USER).unwrap().active_at = Some(Instant::now());
+
+        assert_eq!(scheduler.reschedule(USER, elo("2000")) - Instant::now(), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_users_are_idle() {
+
+        let scheduler = scheduler();
+
+        assert_eq!(scheduler.reschedule(UserId::new(2), elo("2000")) - Instant::now(), Duration::from_secs(600));
+        assert!(!scheduler.entries().contains_key(&UserId::new(2)));
+    }
+
+    fn blocked(kind: &str, id: &str) -> BlockedEntry {
+        BlockedEntry { kind: kind.to_string(), id: id.to_string(), reason: None, added_by: String::from("1"), added_at: 0 }
+    }
+
+    #[test]
+    fn blocklist_blocks_users_and_accounts() {
+
+        let blocklist = Blocklist::new(vec![blocked("discord", "1"), blocked("faceit", "banned")]);
+
+        assert!(blocklist.blocks("1", Some("faceit")));
+        assert!(blocklist.blocks("1", None));
+        assert!(blocklist.blocks("2", Some("banned")));
+        assert!(!blocklist.blocks("2", Some("faceit")));
+        assert!(!blocklist.blocks("2", None));
+    }
+
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+use tracing::{error, info};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+// A task which ran this long before failing starts over with the shortest backoff.
+const HEALTHY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Handed to supervised tasks, resolves once the bot is shutting down.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    pub async fn wait(mut self) {
+        let _ = self.receiver.wait_for(|triggered| *triggered).await;
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskState {
+    Running,
+    /// Crashed and waiting for its backoff before starting again.
+    Restarting,
+    Stopped,
+}
+
+impl TaskState {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Running => "running",
+            TaskState::Restarting => "restarting",
+            TaskState::Stopped => "stopped",
+        }
+    }
+
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskHealth {
+    pub state: TaskState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub started_at: Instant,
+}
+
+struct Inner {
+    shutdown: watch::Sender<bool>,
+    health: Mutex<BTreeMap<&'static str, TaskHealth>>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    /// Commands and event handlers currently running, see `track`.
+    in_flight: watch::Sender<usize>,
+}
+
+/// Held while a command or event handler runs, shutdown waits until every one is dropped.
+pub struct Work {
+    supervisor: Supervisor,
+}
+
+impl Drop for Work {
+    fn drop(&mut self) {
+        self.supervisor.inner.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
+/// Runs background tasks, restarting them with backoff when they crash, and stops them on shutdown.
+#[derive(Clone)]
+pub struct Supervisor {
+    inner: Arc<Inner>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor {
+            inner: Arc::new(Inner {
+                shutdown: watch::channel(false).0,
+                health: Mutex::new(BTreeMap::new()),
+                handles: Mutex::new(Vec::new()),
+                in_flight: watch::channel(0).0,
+            }),
+        }
+    }
+}
+
+impl Supervisor {
+
+    pub fn shutdown_signal(&self) -> Shutdown {
+        Shutdown { receiver: self.inner.shutdown.subscribe() }
+    }
+
+    /// Keeps the task running until shutdown, `task` is called again to start it over after a crash.
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: Fn(Shutdown) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+
+        let supervisor = self.clone();
+
+        let handle = tokio::spawn(async move {
+
+            let mut backoff = MIN_BACKOFF;
+
+            loop {
+
+                let started_at = Instant::now();
+
+                supervisor.update(name, |health| {
+                    health.state = TaskState::Running;
+                    health.started_at = started_at;
+                });
+
+                // Running the task in its own tokio task turns a panic into an error instead of taking the supervisor down.
+                let result = tokio::spawn(task(supervisor.shutdown_signal())).await;
+
+                if supervisor.shutdown_signal().is_triggered() {
+                    supervisor.update(name, |health| health.state = TaskState::Stopped);
+                    info!("Background task '{}' stopped.", name);
+                    return;
+                }
+
+                let reason = match result {
+                    Ok(()) => String::from("returned unexpectedly"),
+                    Err(e) if e.is_panic() => {
+                        let payload = e.into_panic();
+                        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                            .or(payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_default();
+                        format!("panicked: {}", message)
+                    },
+                    Err(e) => e.to_string(),
+                };
+
+                if started_at.elapsed() >= HEALTHY_AFTER {
+                    backoff = MIN_BACKOFF;
+                }
+
+                error!("Background task '{}' {}, restarting in {} seconds.", name, reason, backoff.as_secs());
+
+                supervisor.update(name, |health| {
+                    health.state = TaskState::Restarting;
+                    health.restarts += 1;
+                    health.last_error = Some(reason);
+                });
+
+                tokio::select! {
+                    _ = sleep(backoff) => {},
+                    _ = supervisor.shutdown_signal().wait() => {
+                        supervisor.update(name, |health| health.state = TaskState::Stopped);
+                        return;
+                    },
+                }
+
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        });
+
+        self.inner.handles.lock().unwrap_or_else(|e| e.into_inner()).push(handle);
+    }
+
+    /// Marks work which has no shutdown signal of its own, such as a command, as running until the guard is dropped.
+    pub fn track(&self) -> Work {
+        self.inner.in_flight.send_modify(|count| *count += 1);
+        Work { supervisor: self.clone() }
+    }
+
+    /// Current health of every supervised task, by name.
+    pub fn health(&self) -> BTreeMap<&'static str, TaskHealth> {
+        self.inner.health.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Tells every task to stop and waits for them and any tracked work to finish, up to `limit`.
+    ///
+    /// Every database write commits on its own, so there is nothing left to flush once the work is done.
+    pub async fn shutdown(&self, limit: Duration) {
+
+        self.inner.shutdown.send_replace(true);
+
+        let handles = std::mem::take(&mut *self.inner.handles.lock().unwrap_or_else(|e| e.into_inner()));
+        let mut in_flight = self.inner.in_flight.subscribe();
+
+        let finished = timeout(limit, async {
+            for handle in handles {
+                let _ = handle.await;
+            }
+            let _ = in_flight.wait_for(|count| *count == 0).await;
+        }).await;
+
+        if finished.is_err() {
+            error!("Background tasks and running commands did not finish within {} seconds.", limit.as_secs());
+        }
+    }
+
+    fn update(&self, name: &'static str, change: impl FnOnce(&mut TaskHealth)) {
+        let mut health = self.inner.health.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = health.entry(name).or_insert_with(|| TaskHealth {
+            state: TaskState::Running,
+            restarts: 0,
+            last_error: None,
+            started_at: Instant::now(),
+        });
+        change(entry);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn crashed_task_restarts_with_backoff() {
+
+        let supervisor = Supervisor::default();
+        let starts = Arc::new(Mutex::new(Vec::new()));
+
+        let task_starts = starts.clone();
+        supervisor.spawn("flaky", move |shutdown| {
+            let starts = task_starts.clone();
+            async move {
+                let attempt = {
+                    let mut starts = starts.lock().unwrap();
+                    starts.push(tokio::time::Instant::now());
+                    starts.len()
+                };
+                if attempt < 3 {
+                    panic!("attempt {}", attempt);
+                }
+                shutdown.wait().await;
+            }
+        });
+
+        while starts.lock().unwrap().len() < 3 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let starts = starts.lock().unwrap().clone();
+        assert!(starts[1] - starts[0] >= MIN_BACKOFF);
+        assert!(starts[2] - starts[1] >= MIN_BACKOFF * 2);
+
+        let health = supervisor.health()["flaky"].clone();
+        assert_eq!(health.state, TaskState::Running);
+        assert_eq!(health.restarts, 2);
+        assert_eq!(health.last_error.as_deref(), Some("panicked: attempt 2"));
+
+        supervisor.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(supervisor.health()["flaky"].state, TaskState::Stopped);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn returning_task_is_restarted() {
+
+        let supervisor = Supervisor::default();
+        let runs = Arc::new(Mutex::new(0));
+
+        let task_runs = runs.clone();
+        supervisor.spawn("short", move |shutdown| {
+            let runs = task_runs.clone();
+            async move {
+                *runs.lock().unwrap() += 1;
+                if *runs.lock().unwrap() > 1 {
+                    shutdown.wait().await;
+                }
+            }
+        });
+
+        while *runs.lock().unwrap() < 2 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let health = supervisor.health()["short"].clone();
+        assert_eq!(health.restarts, 1);
+        assert_eq!(health.last_error.as_deref(), Some("returned unexpectedly"));
+
+        supervisor.shutdown(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_waits_for_tracked_work() {
+
+        let supervisor = Supervisor::default();
+        let work = supervisor.track();
+
+        let stopping = supervisor.clone();
+        let shutdown = tokio::spawn(async move { stopping.shutdown(Duration::from_secs(30)).await });
+
+        sleep(Duration::from_secs(1)).await;
+        assert!(!shutdown.is_finished());
+
+        drop(work);
+        shutdown.await.unwrap();
+    }
+
+}
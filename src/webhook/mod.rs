@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use serenity::all::UserId;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{error, info};
+use crate::database::Database;
+use crate::syncer::SyncScheduler;
+use crate::tasks::Shutdown;
+
+pub const WEBHOOK_PATH: &str = "/faceit/webhook";
+
+/// Receiver settings, the receiver only runs when a secret is configured.
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    pub port: u16,
+    /// Header Faceit is told to send the secret in when creating the webhook subscription.
+    pub header: String,
+    pub secret: String,
+}
+
+/// The parts of a Faceit webhook event the bot cares about.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebhookEvent {
+    pub event: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+impl WebhookEvent {
+
+    /// Faceit player IDs of everyone on the match rosters.
+    pub fn player_ids(&self) -> HashSet<String> {
+        self.payload.get("teams")
+            .and_then(|teams| teams.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|team| team.get("roster").and_then(|roster| roster.as_array()))
+            .flatten()
+            .filter_map(|player| player.get("id").or(player.get("player_id")).and_then(|id| id.as_str()))
+            .map(String::from)
+            .collect()
+    }
+
+}
+
+#[derive(Clone)]
+struct ReceiverState {
+    config: WebhookConfig,
+    /// Rosters of finished matches, looked up and enqueued after responding.
+    finished: mpsc::UnboundedSender<HashSet<String>>,
+}
+
+fn router(config: WebhookConfig, finished: mpsc::UnboundedSender<HashSet<String>>) -> Router {
+    Router::new()
+        .route(WEBHOOK_PATH, post(receive))
+        .with_state(ReceiverState { config, finished })
+}
+
+/// Rosters of finished matches on their way from the receiver to `enqueue_finished`, kept outside both so either can restart on its own.
+pub type Rosters = Arc<Mutex<mpsc::UnboundedReceiver<HashSet<String>>>>;
+
+pub fn queue() -> (mpsc::UnboundedSender<HashSet<String>>, Rosters) {
+    let (finished, rosters) = mpsc::unbounded_channel();
+    (finished, Arc::new(Mutex::new(rosters)))
+}
+
+pub async fn serve(config: WebhookConfig, finished: mpsc::UnboundedSender<HashSet<String>>, shutdown: Shutdown) {
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+
+    let app = router(config, finished);
+
+    info!("Listening for Faceit webhooks on {}", addr);
+
+    let server = axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.wait());
+
+    if let Err(e) = server.await {
+        error!("Faceit webhook receiver stopped: {}", e);
+    }
+}
+
+async fn receive(State(state): State<ReceiverState>, headers: HeaderMap, body: Bytes) -> StatusCode {
+
+    let provided = headers.get(state.config.header.as_str()).map(|value| value.as_bytes()).unwrap_or_default();
+
+    if !constant_time_eq(provided, state.config.secret.as_bytes()) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let event: WebhookEvent = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(e) => {
+            error!("Could not parse Faceit webhook event: {}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    if event.event != "match_status_finished" {
+        return StatusCode::OK;
+    }
+
+    // Faceit retries slow deliveries, so the lookups happen after responding.
+    if state.finished.send(event.player_ids()).is_err() {
+        error!("Could not queue finished match, the receiver is shutting down.");
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    StatusCode::OK
+}
+
+/// Looks up the players of finished matches and has the scheduler refresh them, until shutdown.
+pub async fn enqueue_finished(rosters: Rosters, database: Database, scheduler: SyncScheduler, shutdown: Shutdown) {
+
+    let mut rosters = rosters.lock().await;
+
+    loop {
+        let player_ids = tokio::select! {
+            player_ids = rosters.recv() => player_ids,
+            _ = shutdown.clone().wait() => return,
+        };
+
+        let Some(player_ids) = player_ids else {
+            return;
+        };
+
+        enqueue_players(database, &scheduler, player_ids).await;
+    }
+}
+
+async fn enqueue_players(database: Database, scheduler: &SyncScheduler, player_ids: HashSet<String>) {
+
+    for faceit_id in player_ids {
+
+        let discord_ids = match database.fetch_discord_ids(faceit_id.clone()).await {
+            Ok(discord_ids) => discord_ids,
+            Err(e) => {
+                error!("Could not look up Faceit user '{}': {}", faceit_id, e);
+                continue;
+            }
+        };
+
+        for discord_id in discord_ids {
+            let Ok(u64_id) = discord_id.parse::<u64>() else {
+                continue;
+            };
+
+            info!("Match finished for Faceit user '{}', refreshing.", faceit_id);
+            scheduler.enqueue_match(UserId::new(u64_id)).await;
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "X-Faceit-Secret";
+    const SECRET: &str = "correct horse";
+
+    /// Posts events to a receiver the same way Faceit does.
+    struct StandInSender {
+        url: String,
+        header: String,
+        secret: String,
+    }
+
+    impl StandInSender {
+
+        async fn send(&self, event: &WebhookEvent) -> Result<reqwest::StatusCode, anyhow::Error> {
+            let response = reqwest::Client::new()
+                .post(&self.url)
+                .header(self.header.as_str(), self.secret.as_str())
+                .json(event)
+                .send()
+                .await?;
+
+            Ok(response.status())
+        }
+
+    }
+
+    fn match_finished(match_id: &str, player_ids: &[String]) -> WebhookEvent {
+        let roster: Vec<serde_json::Value> = player_ids.iter()
+            .map(|id| serde_json::json!({ "id": id }))
+            .collect();
+
+        WebhookEvent {
+            event: String::from("match_status_finished"),
+            payload: serde_json::json!({ "id": match_id, "teams": [{ "roster": roster }] }),
+        }
+    }
+
+    /// Starts a receiver on a free port, returning a sender for it and the queue of finished rosters.
+    fn start_receiver() -> (StandInSender, mpsc::UnboundedReceiver<HashSet<String>>) {
+
+        let config = WebhookConfig { port: 0, header: HEADER.to_string(), secret: SECRET.to_string() };
+
+        let (finished, rosters) = mpsc::unbounded_channel();
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(router(config, finished).into_make_service());
+
+        let sender = StandInSender {
+            url: format!("http://{}{}", server.local_addr(), WEBHOOK_PATH),
+            header: HEADER.to_string(),
+            secret: SECRET.to_string(),
+        };
+
+        tokio::spawn(server);
+
+        (sender, rosters)
+    }
+
+    fn ids(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn wrong_secret_is_unauthorized() {
+
+        let (mut sender, mut rosters) = start_receiver();
+        sender.secret = String::from("wrong");
+
+        let event = match_finished("match", &[String::from("player")]);
+
+        assert_eq!(sender.send(&event).await.unwrap(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(rosters.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn finished_match_enqueues_players() {
+
+        let (sender, mut rosters) = start_receiver();
+
+        let event = match_finished("match", &[String::from("first"), String::from("second")]);
+
+        assert_eq!(sender.send(&event).await.unwrap(), reqwest::StatusCode::OK);
+        assert_eq!(rosters.recv().await, Some(ids(&["first", "second"])));
+    }
+
+    #[tokio::test]
+    async fn other_events_are_ignored() {
+
+        let (sender, mut rosters) = start_receiver();
+
+        let event = WebhookEvent { event: String::from("match_status_ready"), payload: serde_json::Value::Null };
+
+        assert_eq!(sender.send(&event).await.unwrap(), reqwest::StatusCode::OK);
+        assert!(rosters.try_recv().is_err());
+    }
+
+    #[test]
+    fn player_ids_from_every_roster() {
+
+        let event: WebhookEvent = serde_json::from_value(serde_json::json!({
+            "event": "match_status_finished",
+            "payload": {
+                "teams": [
+                    { "roster": [{ "id": "first" }, { "player_id": "second" }] },
+                    { "roster": [{ "id": "third" }, { "nickname": "no id" }] },
+                    { "name": "no roster" }
+                ]
+            }
+        })).unwrap();
+
+        assert_eq!(event.player_ids(), ids(&["first", "second", "third"]));
+    }
+
+    #[test]
+    fn player_ids_without_teams() {
+
+        let event: WebhookEvent = serde_json::from_value(serde_json::json!({ "event": "match_status_finished" })).unwrap();
+
+        assert!(event.player_ids().is_empty());
+    }
+
+}